@@ -0,0 +1,566 @@
+//! Audit log of lookups
+//!
+//! When `[audit] log` is set, every number checked appends two JSON lines to
+//! the log: a `started` entry naming the modules about to be run before any
+//! request is sent, then a `finished` entry with the outcome of each module.
+//! A run that is killed halfway still leaves a record of what it set out to
+//! check.
+//! Entries are chained like results files (see [`crate::custody`]): each one
+//! stores the hash of the entry before it and its own HMAC, keyed with
+//! `IGNORANT_CUSTODY_KEY`, so `ignorant audit verify` finds any entry that was
+//! edited or removed.
+//!
+//! Removing entries from the end leaves a valid chain, so the log is anchored:
+//! `<log>.head` holds the number of entries and the hash of the last one,
+//! sealed with the same key and rewritten on every append. A log that no
+//! longer matches its anchor fails verification and is not appended to.
+//!
+//! `ignorant purge` rewrites the log without the entries it removes, and adds
+//! a `purge` record saying who purged what, how many entries went and the
+//! head of the log before the purge, so an anchor kept from before still
+//! leads to it. The new log is written beside the old one and renamed over
+//! it, then the anchor is updated; an anchor still recording the head the
+//! last purge replaced means the purge was interrupted after the rename, and
+//! is accepted.
+//!
+//! With `hash_numbers = true`, the log holds an HMAC-SHA256 of the number,
+//! keyed with `IGNORANT_AUDIT_KEY`, instead of the number itself. Anyone with
+//! the key can still tell whether a given number was checked. With
+//! `[privacy] pseudonymize = true` and no key of its own, the log uses the
+//! same pseudonyms as the rest of the stored data.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::batch::Target;
+use crate::config::AuditConfig;
+use crate::custody::{ChainKey, GENESIS};
+use crate::privacy::{self, Pseudonymizer};
+use crate::{CheckResult, Outcome};
+
+/// Environment variable holding the key numbers in the audit log are hashed with
+pub const KEY_ENV: &str = "IGNORANT_AUDIT_KEY";
+
+/// Whether an entry announces checks or reports how they went
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Written before any request is sent for the number
+    Started,
+    /// Written once every check on the number is over
+    #[default]
+    Finished,
+}
+
+/// One number checked during a run
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Entry {
+    pub timestamp: DateTime<Utc>,
+    pub run_id: String,
+    pub host: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub case_id: Option<String>,
+    /// `+<country code> <phone>`, or `hmac-sha256:<hex>` with hashed numbers
+    pub number: String,
+    /// Entries written before this field existed all report outcomes
+    #[serde(default)]
+    pub stage: Stage,
+    /// Modules about to be run on the number, in `started` entries
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modules: Vec<String>,
+    /// Outcome of each module run on the number, in `finished` entries
+    pub results: BTreeMap<String, Outcome>,
+}
+
+/// Who is changing the log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Actor {
+    pub host: String,
+    pub operator: Option<String>,
+}
+
+/// Entries removed by `ignorant purge`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Purge {
+    pub timestamp: DateTime<Utc>,
+    pub host: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>,
+    /// What was purged (`expired`, `case <ID>` or `number`)
+    pub purged: String,
+    /// Lookup entries removed
+    pub removed: usize,
+    /// Lookup entries kept
+    pub kept: usize,
+    /// The log before the purge
+    pub previous: Head,
+}
+
+/// A line of the audit log
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum Line {
+    Purge(Purge),
+    Lookup(Entry),
+}
+
+/// Length and last hash of a log, as recorded in its anchor
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Head {
+    pub entries: usize,
+    /// Hash of the last entry, or the genesis hash for an empty log
+    pub hash: String,
+}
+
+impl Head {
+    fn empty() -> Self {
+        Self { entries: 0, hash: GENESIS.to_owned() }
+    }
+}
+
+/// `<log>.head`, where the anchor of a log is kept
+fn anchor_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".head");
+    PathBuf::from(name)
+}
+
+/// Lock `<log>.lock`, which unlike the log is never replaced, until the returned file is closed
+fn lock(path: &Path) -> Result<File> {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".lock");
+    let lock = PathBuf::from(name);
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock)
+        .with_context(|| format!("failed to open audit log lock {}", lock.display()))?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// Replace the file at `path` with `contents` in one step, so a crash leaves either the old or the new file
+fn replace(path: &Path, contents: &str) -> Result<()> {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".tmp");
+    let temporary = PathBuf::from(name);
+    let mut file = File::create(&temporary)?;
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// Record `head` as the state of the log at `path`
+fn write_anchor(path: &Path, head: &Head, key: &ChainKey) -> Result<()> {
+    let mut value = serde_json::to_value(head)?;
+    let mac = key.digest(&value)?;
+    value["mac"] = Value::String(mac);
+    let anchor = anchor_path(path);
+    replace(&anchor, &(serde_json::to_string(&value)? + "\n"))
+        .with_context(|| format!("failed to write audit log anchor {}", anchor.display()))
+}
+
+/// Check that the log at `path` is as long as its anchor says and ends where it says
+///
+/// `purged` is the head the log's last line replaced, when that line is a
+/// purge record: an anchor still recording it is one a purge was
+/// interrupted before updating.
+fn check_anchor(path: &Path, head: &Head, purged: Option<&Head>, key: &ChainKey) -> Result<()> {
+    let anchor = anchor_path(path);
+    let contents = match fs::read_to_string(&anchor) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && head.entries == 0 => return Ok(()),
+        Err(e) => {
+            return Err(e).with_context(|| format!("failed to read audit log anchor {}", anchor.display()));
+        }
+    };
+    let mut value: Value = serde_json::from_str(&contents).context("anchor is not JSON")?;
+    let Some(Value::String(mac)) = value.as_object_mut().and_then(|object| object.remove("mac")) else {
+        bail!("anchor has no mac");
+    };
+    if key.digest(&value)? != mac {
+        bail!("anchor was modified, or sealed with another key");
+    }
+    let anchored: Head = serde_json::from_value(value).context("unknown anchor")?;
+    if purged == Some(&anchored) {
+        return Ok(());
+    }
+    if anchored.entries != head.entries {
+        bail!(
+            "log has {} entries but its anchor records {}: entries were removed from the end",
+            head.entries,
+            anchored.entries
+        );
+    }
+    if anchored.hash != head.hash {
+        bail!("last entry does not match the anchor");
+    }
+    Ok(())
+}
+
+/// Append-only audit log file
+pub struct AuditLog {
+    path: PathBuf,
+    pseudonymizer: Option<Pseudonymizer>,
+    key: ChainKey,
+}
+
+impl AuditLog {
+    /// Audit log described by the configuration, if one is configured
+    pub fn open(config: &AuditConfig, pseudonymizer: Option<&Pseudonymizer>) -> Result<Option<Self>> {
+        let Some(path) = &config.log else {
+            return Ok(None);
+        };
+        let pseudonymizer = if config.hash_numbers {
+            Some(Pseudonymizer::from_env(KEY_ENV).context("[audit] hash_numbers is on")?)
+        } else {
+            pseudonymizer.cloned()
+        };
+        let key = ChainKey::from_env().context("[audit] log is set")?;
+        Ok(Some(Self { path: path.clone(), pseudonymizer, key }))
+    }
+
+    /// Pseudonymizer numbers are written to the log with, if any
+    pub fn pseudonymizer(&self) -> Option<&Pseudonymizer> {
+        self.pseudonymizer.as_ref()
+    }
+
+    /// Entry announcing the checks about to be run on one number
+    pub fn started(&self, template: &Entry, target: &Target, modules: &[&str]) -> Entry {
+        Entry {
+            timestamp: Utc::now(),
+            number: privacy::stored(self.pseudonymizer.as_ref(), target),
+            stage: Stage::Started,
+            modules: modules.iter().map(|module| (*module).to_owned()).collect(),
+            results: BTreeMap::new(),
+            ..template.clone()
+        }
+    }
+
+    /// Entry for the checks run on one number
+    pub fn entry(&self, template: &Entry, target: &Target, results: &[CheckResult]) -> Entry {
+        Entry {
+            timestamp: Utc::now(),
+            number: privacy::stored(self.pseudonymizer.as_ref(), target),
+            stage: Stage::Finished,
+            modules: Vec::new(),
+            results: results.iter().map(|result| (result.name.clone(), result.outcome)).collect(),
+            ..template.clone()
+        }
+    }
+
+    /// Append an entry, chained to the last one in the log
+    pub fn append(&self, entry: &Entry) -> Result<()> {
+        // Held until the entry and the anchor are written, so concurrent runs
+        // cannot fork the chain nor append to a log a purge is replacing
+        let _lock = lock(&self.path)?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&self.path)
+            .with_context(|| format!("failed to open audit log {}", self.path.display()))?;
+        let damaged = || format!("audit log {} is damaged", self.path.display());
+        let (head, purged) = last_hash(&mut file).with_context(damaged)?;
+        check_anchor(&self.path, &head, purged.as_ref(), &self.key).with_context(damaged)?;
+
+        let mut value = serde_json::to_value(entry)?;
+        let hash = self.key.seal(&mut value, head.hash)?;
+        let line = serde_json::to_string(&value)? + "\n";
+        file.write_all(line.as_bytes())
+            .with_context(|| format!("failed to write audit log {}", self.path.display()))?;
+        write_anchor(&self.path, &Head { entries: head.entries + 1, hash }, &self.key)
+    }
+}
+
+/// Number of entries and hash of the last one, with the head it replaced if it is a purge record
+fn last_hash(file: &mut File) -> Result<(Head, Option<Head>)> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents)?;
+    let Some(line) = contents.lines().next_back() else {
+        return Ok((Head::empty(), None));
+    };
+    let value: Value = serde_json::from_str(line).context("last entry is not JSON")?;
+    let Some(hash) = value.get("hash").and_then(Value::as_str) else {
+        bail!("last entry has no hash");
+    };
+    let head = Head { entries: contents.lines().count(), hash: hash.to_owned() };
+    Ok((head, replaced_head(serde_json::from_value(value).ok())))
+}
+
+/// Head a purge record replaced
+fn replaced_head(line: Option<Line>) -> Option<Head> {
+    match line? {
+        Line::Purge(purge) => Some(purge.previous),
+        Line::Lookup(_) => None,
+    }
+}
+
+/// Check an audit log for edits and truncation, returning its head
+pub fn verify(path: &Path, key: &ChainKey) -> Result<Head> {
+    Ok(read_lines(path, key)?.1)
+}
+
+/// Lookup entries of an audit log, checked for edits and truncation
+pub fn read(path: &Path, key: &ChainKey) -> Result<Vec<Entry>> {
+    let (lines, _) = read_lines(path, key)?;
+    Ok(lines
+        .into_iter()
+        .filter_map(|line| match line {
+            Line::Lookup(entry) => Some(entry),
+            Line::Purge(_) => None,
+        })
+        .collect())
+}
+
+fn read_lines(path: &Path, key: &ChainKey) -> Result<(Vec<Line>, Head)> {
+    let contents = fs::read_to_string(path).with_context(|| format!("failed to read audit log {}", path.display()))?;
+    let (lines, head) = parse(&contents, key)?;
+    check_anchor(path, &head, replaced_head(lines.last().cloned()).as_ref(), key)?;
+    Ok((lines, head))
+}
+
+fn parse(contents: &str, key: &ChainKey) -> Result<(Vec<Line>, Head)> {
+    let mut prev = GENESIS.to_owned();
+    let mut entries = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let number = index + 1;
+        let mut value: Value =
+            serde_json::from_str(line).with_context(|| format!("line {number}: not a JSON entry"))?;
+        let Some(Value::String(hash)) = value.as_object_mut().and_then(|object| object.remove("hash")) else {
+            bail!("line {number}: entry has no hash");
+        };
+        if value.get("prev").and_then(Value::as_str) != Some(prev.as_str()) {
+            bail!("line {number}: chain broken, an entry was removed, inserted or reordered");
+        }
+        if key.digest(&value)? != hash {
+            bail!("line {number}: entry was modified, or the log was sealed with another key");
+        }
+        entries.push(serde_json::from_value(value).with_context(|| format!("line {number}: unknown entry"))?);
+        prev = hash;
+    }
+    let head = Head { entries: entries.len(), hash: prev };
+    Ok((entries, head))
+}
+
+/// Drop the lookup entries for which `keep` is false, recording the purge
+///
+/// Used by `ignorant purge`, `purged` describing what was asked for. The
+/// log is chained anew and ends with a [`Purge`] record. Returns the number
+/// of entries removed.
+pub fn retain(path: &Path, key: &ChainKey, actor: &Actor, purged: &str, mut keep: impl FnMut(&Entry) -> bool) -> Result<usize> {
+    let lock = lock(path)?;
+    let contents = fs::read_to_string(path).with_context(|| format!("failed to read audit log {}", path.display()))?;
+    let damaged = || format!("audit log {} is damaged", path.display());
+    let (mut lines, head) = parse(&contents, key).with_context(damaged)?;
+    check_anchor(path, &head, replaced_head(lines.last().cloned()).as_ref(), key).with_context(damaged)?;
+    let lookups = |lines: &[Line]| lines.iter().filter(|line| matches!(line, Line::Lookup(_))).count();
+    let before = lookups(&lines);
+    // Earlier purge records are kept whatever is purged now
+    lines.retain(|line| match line {
+        Line::Lookup(entry) => keep(entry),
+        Line::Purge(_) => true,
+    });
+    let kept = lookups(&lines);
+    lines.push(Line::Purge(Purge {
+        timestamp: Utc::now(),
+        host: actor.host.clone(),
+        operator: actor.operator.clone(),
+        purged: purged.to_owned(),
+        removed: before - kept,
+        kept,
+        previous: head,
+    }));
+
+    let mut prev = GENESIS.to_owned();
+    let mut rewritten = String::new();
+    for line in &lines {
+        let mut value = serde_json::to_value(line)?;
+        prev = key.seal(&mut value, prev)?;
+        rewritten.push_str(&serde_json::to_string(&value)?);
+        rewritten.push('\n');
+    }
+    replace(path, &rewritten).with_context(|| format!("failed to write audit log {}", path.display()))?;
+    write_anchor(path, &Head { entries: lines.len(), hash: prev }, key)?;
+    drop(lock);
+    Ok(before - kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key() -> ChainKey {
+        ChainKey::new("custody secret")
+    }
+
+    fn log(path: &Path, number_key: Option<&str>) -> AuditLog {
+        AuditLog { path: path.to_owned(), pseudonymizer: number_key.map(Pseudonymizer::new), key: key() }
+    }
+
+    fn template() -> Entry {
+        Entry {
+            timestamp: Utc::now(),
+            run_id: "7d9f3c4e-0000-4000-8000-000000000000".to_owned(),
+            host: "workstation".to_owned(),
+            operator: Some("J. Doe".to_owned()),
+            case_id: None,
+            number: String::new(),
+            stage: Stage::Finished,
+            modules: Vec::new(),
+            results: BTreeMap::new(),
+        }
+    }
+
+    fn target(phone: &str) -> Target {
+        Target { country_code: "33".to_owned(), phone: phone.to_owned() }
+    }
+
+    #[test]
+    fn test_append_and_verify() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let log = log(&path, None);
+        let results = [CheckResult::new("instagram", "instagram.com", "other").with_exists(true)];
+
+        log.append(&log.entry(&template(), &target("644637111"), &results)).unwrap();
+        log.append(&log.entry(&template(), &target("644637112"), &[])).unwrap();
+
+        assert_eq!(verify(&path, &key()).unwrap().entries, 2);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(contents.contains("\"number\":\"+33 644637111\""));
+        assert!(contents.contains("\"instagram\":\"found\""));
+    }
+
+    #[test]
+    fn test_detects_edits_and_removals() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let log = log(&path, None);
+        for phone in ["644637111", "644637112", "644637113"] {
+            log.append(&log.entry(&template(), &target(phone), &[])).unwrap();
+        }
+        let contents = std::fs::read_to_string(&path).unwrap();
+
+        std::fs::write(&path, contents.replace("644637112", "644637119")).unwrap();
+        assert!(verify(&path, &key()).unwrap_err().to_string().contains("line 2: entry was modified"));
+
+        let lines: Vec<&str> = contents.lines().collect();
+        std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert!(verify(&path, &key()).unwrap_err().to_string().contains("line 2: chain broken"));
+
+        // Re-chaining the log without the key does not pass either
+        std::fs::write(&path, &contents).unwrap();
+        assert!(verify(&path, &ChainKey::new("guessed")).unwrap_err().to_string().contains("another key"));
+    }
+
+    #[test]
+    fn test_detects_truncation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let log = log(&path, None);
+        for phone in ["644637111", "644637112", "644637113"] {
+            log.append(&log.entry(&template(), &target(phone), &[])).unwrap();
+        }
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        std::fs::write(&path, format!("{}\n{}\n", lines[0], lines[1])).unwrap();
+
+        let err = verify(&path, &key()).unwrap_err();
+        assert!(err.to_string().contains("anchor records 3"), "{err}");
+        assert!(log.append(&log.entry(&template(), &target("644637114"), &[])).is_err());
+
+        std::fs::remove_file(anchor_path(&path)).unwrap();
+        assert!(verify(&path, &key()).is_err());
+    }
+
+    #[test]
+    fn test_retain_records_purge() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let log = log(&path, None);
+        for phone in ["644637111", "644637112", "644637113"] {
+            log.append(&log.entry(&template(), &target(phone), &[])).unwrap();
+        }
+        let before = verify(&path, &key()).unwrap();
+        let actor = Actor { host: "workstation".to_owned(), operator: Some("J. Doe".to_owned()) };
+
+        let removed = retain(&path, &key(), &actor, "number", |entry| entry.number != "+33 644637112").unwrap();
+        log.append(&log.entry(&template(), &target("644637114"), &[])).unwrap();
+
+        assert_eq!(removed, 1);
+        let numbers: Vec<String> = read(&path, &key()).unwrap().into_iter().map(|entry| entry.number).collect();
+        assert_eq!(numbers, ["+33 644637111", "+33 644637113", "+33 644637114"]);
+        let (lines, head) = read_lines(&path, &key()).unwrap();
+        assert_eq!(head.entries, 4);
+        let Line::Purge(purge) = &lines[2] else { panic!("no purge record: {lines:?}") };
+        assert_eq!((purge.purged.as_str(), purge.removed, purge.kept), ("number", 1, 2));
+        assert_eq!(purge.operator.as_deref(), Some("J. Doe"));
+        assert_eq!(purge.previous, before);
+        assert!(!std::fs::read_to_string(&path).unwrap().contains("644637112"));
+    }
+
+    #[test]
+    fn test_started_entry_before_outcome() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let log = log(&path, None);
+
+        log.append(&log.started(&template(), &target("644637111"), &["instagram", "snapchat"])).unwrap();
+        log.append(&log.entry(&template(), &target("644637111"), &[])).unwrap();
+
+        let entries = read(&path, &key()).unwrap();
+        assert_eq!(entries[0].stage, Stage::Started);
+        assert_eq!(entries[0].modules, ["instagram", "snapchat"]);
+        assert_eq!(entries[1].stage, Stage::Finished);
+        assert!(entries[1].modules.is_empty());
+    }
+
+    #[test]
+    fn test_purge_interrupted_before_anchor_update() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let log = log(&path, None);
+        for phone in ["644637111", "644637112"] {
+            log.append(&log.entry(&template(), &target(phone), &[])).unwrap();
+        }
+        let anchor = std::fs::read_to_string(anchor_path(&path)).unwrap();
+        let actor = Actor { host: "workstation".to_owned(), operator: None };
+
+        retain(&path, &key(), &actor, "number", |entry| entry.number != "+33 644637112").unwrap();
+        // The new log was renamed into place, but the anchor was never rewritten
+        std::fs::write(anchor_path(&path), &anchor).unwrap();
+
+        assert_eq!(verify(&path, &key()).unwrap().entries, 2);
+        log.append(&log.entry(&template(), &target("644637113"), &[])).unwrap();
+        assert_eq!(verify(&path, &key()).unwrap().entries, 3);
+        let files: Vec<_> = std::fs::read_dir(dir.path()).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        assert!(!files.iter().any(|name| name.to_string_lossy().ends_with(".tmp")), "{files:?}");
+    }
+
+    #[test]
+    fn test_hashed_numbers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let log = log(&path, Some("secret"));
+
+        log.append(&log.entry(&template(), &target("644637111"), &[])).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("644637111"));
+        assert!(contents.contains(&Pseudonymizer::new("secret").pseudonym(&target("644637111"))));
+        assert_eq!(verify(&path, &key()).unwrap().entries, 1);
+    }
+}
@@ -0,0 +1,92 @@
+//! Batch input files
+//!
+//! A batch file lists one number per line as `<COUNTRY_CODE> <PHONE>`, the
+//! same order as on the command line. A leading `+` on the country code is
+//! accepted; blank lines and lines starting with `#` are ignored.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+
+/// A number to check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Target {
+    pub country_code: String,
+    pub phone: String,
+}
+
+impl Target {
+    /// The number in E.164 form (`+33644637111`)
+    pub fn e164(&self) -> String {
+        format!("+{}{}", self.country_code, self.phone)
+    }
+}
+
+/// `+<country code> <phone>`, as printed and stored
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "+{} {}", self.country_code, self.phone)
+    }
+}
+
+/// Parse the contents of a batch file
+pub fn parse(contents: &str) -> Result<Vec<Target>> {
+    let mut targets = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let (Some(country_code), Some(phone), None) = (fields.next(), fields.next(), fields.next()) else {
+            bail!("line {}: expected `<COUNTRY_CODE> <PHONE>`", number + 1);
+        };
+        targets.push(Target {
+            country_code: country_code.trim_start_matches('+').to_owned(),
+            phone: phone.to_owned(),
+        });
+    }
+    if targets.is_empty() {
+        bail!("no numbers to check");
+    }
+    Ok(targets)
+}
+
+/// Read and parse a batch file
+pub fn load(path: &Path) -> Result<Vec<Target>> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("failed to read batch file {}", path.display()))?;
+    parse(&contents).with_context(|| format!("invalid batch file {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_batch() {
+        let targets = parse("# targets\n33 644637111\n\n+44   7700900000\n").unwrap();
+
+        assert_eq!(
+            targets,
+            [
+                Target { country_code: "33".to_owned(), phone: "644637111".to_owned() },
+                Target { country_code: "44".to_owned(), phone: "7700900000".to_owned() },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_batch_rejects_malformed_line() {
+        let err = parse("33 644637111\n644637111\n").unwrap_err();
+
+        assert!(err.to_string().contains("line 2"));
+    }
+
+    #[test]
+    fn test_parse_batch_empty() {
+        assert!(parse("# nothing yet\n").is_err());
+    }
+}
@@ -0,0 +1,272 @@
+//! Per-module circuit breaker
+//!
+//! During a batch, a module whose checks keep failing (errors, timeouts, rate
+//! limits or challenge pages) is skipped for a cool-down period instead of hammering the
+//! platform. Once the cool-down is over a single probe check is let through:
+//! an answer closes the breaker again, another failure reopens it.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::Outcome;
+use crate::config::CircuitBreakerConfig;
+
+/// Where a module's breaker stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    /// Checks run normally
+    Closed,
+    /// Checks are skipped until the cool-down ends
+    Open,
+    /// A probe check is in flight
+    HalfOpen,
+}
+
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Closed => "closed",
+            Self::Open => "open",
+            Self::HalfOpen => "half-open",
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct Entry {
+    failures: u32,
+    open_until: Option<Instant>,
+    probing: bool,
+    trips: u32,
+    skipped: u32,
+}
+
+impl Entry {
+    fn state(&self) -> State {
+        match self.open_until {
+            None => State::Closed,
+            Some(_) if self.probing => State::HalfOpen,
+            Some(_) => State::Open,
+        }
+    }
+}
+
+/// Breaker status of one module, for the run summary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    pub module: String,
+    pub state: State,
+    /// Times the breaker opened
+    pub trips: u32,
+    /// Checks skipped while it was open
+    pub skipped: u32,
+}
+
+/// Circuit breakers for every module, shared across a run
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    modules: Mutex<HashMap<String, Entry>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            modules: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn with_entry<T>(&self, module: &str, f: impl FnOnce(&mut Entry) -> T) -> T {
+        let mut modules = self.modules.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        f(modules.entry(module.to_owned()).or_default())
+    }
+
+    /// Whether `module` may run now; a refused check counts as skipped
+    pub fn allow(&self, module: &str) -> bool {
+        self.allow_at(module, Instant::now())
+    }
+
+    fn allow_at(&self, module: &str, now: Instant) -> bool {
+        if self.config.failure_threshold == 0 {
+            return true;
+        }
+        self.with_entry(module, |entry| match entry.open_until {
+            Some(until) if now < until || entry.probing => {
+                entry.skipped += 1;
+                false
+            }
+            Some(_) => {
+                entry.probing = true;
+                true
+            }
+            None => true,
+        })
+    }
+
+    /// Record the outcome of a check that was allowed to run
+    pub fn record(&self, module: &str, outcome: Outcome) {
+        self.record_at(module, outcome, Instant::now());
+    }
+
+    fn record_at(&self, module: &str, outcome: Outcome, now: Instant) {
+        if self.config.failure_threshold == 0 {
+            return;
+        }
+        let cooldown = Duration::from_secs(self.config.cooldown_secs);
+        let threshold = self.config.failure_threshold;
+        self.with_entry(module, |entry| match outcome {
+            Outcome::Found | Outcome::NotFound | Outcome::InvalidInput => *entry = Entry {
+                trips: entry.trips,
+                skipped: entry.skipped,
+                ..Entry::default()
+            },
+            // A rejected certificate fails every request until the trust store
+            // changes, and a platform that hangs is as down as one that errors
+            Outcome::Error | Outcome::TlsError | Outcome::TimedOut | Outcome::RateLimited | Outcome::Blocked => {
+                entry.failures += 1;
+                if entry.probing || (entry.open_until.is_none() && entry.failures >= threshold) {
+                    entry.open_until = Some(now + cooldown);
+                    entry.probing = false;
+                    entry.trips += 1;
+                }
+            }
+            // Neither an answer nor the platform's fault (an unreadable answer
+            // is the module's): let the next check probe again
+            Outcome::Unexpected | Outcome::Skipped => entry.probing = false,
+        });
+    }
+
+    /// Modules whose breaker is currently open or probing
+    pub fn open_modules(&self) -> Vec<String> {
+        let modules = self.modules.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut open: Vec<String> = modules
+            .iter()
+            .filter(|(_, entry)| entry.state() != State::Closed)
+            .map(|(module, _)| module.clone())
+            .collect();
+        open.sort();
+        open
+    }
+
+    /// Status of every module whose breaker opened at least once
+    pub fn summary(&self) -> Vec<Status> {
+        let modules = self.modules.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let mut summary: Vec<Status> = modules
+            .iter()
+            .filter(|(_, entry)| entry.trips > 0)
+            .map(|(module, entry)| Status {
+                module: module.clone(),
+                state: entry.state(),
+                trips: entry.trips,
+                skipped: entry.skipped,
+            })
+            .collect();
+        summary.sort_by(|a, b| a.module.cmp(&b.module));
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn breaker(failure_threshold: u32) -> CircuitBreaker {
+        CircuitBreaker::new(CircuitBreakerConfig {
+            failure_threshold,
+            cooldown_secs: 60,
+        })
+    }
+
+    #[test]
+    fn test_opens_after_consecutive_failures() {
+        let breaker = breaker(3);
+        let now = Instant::now();
+
+        for outcome in [Outcome::TlsError, Outcome::RateLimited] {
+            assert!(breaker.allow_at("amazon", now));
+            breaker.record_at("amazon", outcome, now);
+        }
+        breaker.record_at("amazon", Outcome::Unexpected, now);
+        assert!(breaker.open_modules().is_empty());
+        breaker.record_at("amazon", Outcome::Error, now);
+
+        assert_eq!(breaker.open_modules(), ["amazon"]);
+        assert!(!breaker.allow_at("amazon", now + Duration::from_secs(30)));
+        assert!(breaker.allow_at("snapchat", now));
+    }
+
+    #[test]
+    fn test_timeouts_count_as_failures() {
+        let breaker = breaker(2);
+        let now = Instant::now();
+
+        breaker.record_at("snapchat", Outcome::TimedOut, now);
+        breaker.record_at("snapchat", Outcome::TimedOut, now);
+
+        assert_eq!(breaker.open_modules(), ["snapchat"]);
+        assert!(!breaker.allow_at("snapchat", now));
+    }
+
+    #[test]
+    fn test_answer_resets_failure_count() {
+        let breaker = breaker(2);
+        let now = Instant::now();
+
+        breaker.record_at("amazon", Outcome::Error, now);
+        breaker.record_at("amazon", Outcome::NotFound, now);
+        breaker.record_at("amazon", Outcome::Error, now);
+
+        assert!(breaker.open_modules().is_empty());
+    }
+
+    #[test]
+    fn test_probe_after_cooldown() {
+        let breaker = breaker(1);
+        let now = Instant::now();
+        breaker.record_at("amazon", Outcome::RateLimited, now);
+        let later = now + Duration::from_secs(61);
+
+        // A single probe is let through, the others wait for its answer
+        assert!(breaker.allow_at("amazon", later));
+        assert!(!breaker.allow_at("amazon", later));
+        breaker.record_at("amazon", Outcome::Found, later);
+
+        assert!(breaker.allow_at("amazon", later));
+        let summary = breaker.summary();
+        assert_eq!(summary.len(), 1);
+        assert_eq!(summary[0].state, State::Closed);
+        assert_eq!(summary[0].trips, 1);
+        assert_eq!(summary[0].skipped, 1);
+    }
+
+    #[test]
+    fn test_failed_probe_reopens() {
+        let breaker = breaker(1);
+        let now = Instant::now();
+        breaker.record_at("amazon", Outcome::Error, now);
+        let later = now + Duration::from_secs(61);
+
+        assert!(breaker.allow_at("amazon", later));
+        breaker.record_at("amazon", Outcome::Error, later);
+
+        assert!(!breaker.allow_at("amazon", later + Duration::from_secs(30)));
+        assert_eq!(breaker.summary()[0].trips, 2);
+        assert_eq!(breaker.summary()[0].state, State::Open);
+    }
+
+    #[test]
+    fn test_disabled() {
+        let breaker = breaker(0);
+        let now = Instant::now();
+
+        for _ in 0..10 {
+            breaker.record_at("amazon", Outcome::Error, now);
+        }
+
+        assert!(breaker.allow_at("amazon", now));
+        assert!(breaker.summary().is_empty());
+    }
+}
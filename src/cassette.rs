@@ -0,0 +1,306 @@
+//! Recorded HTTP traffic
+//!
+//! `--record DIR` saves every request a module sends, with the response it
+//! got, to `DIR/<module>.jsonl`. `--replay DIR` answers the same requests from
+//! those files without touching the network, so a module's parsing can be
+//! checked against captured traffic offline and deterministically.
+//!
+//! A replayed request gets the first unused recorded response to the same
+//! method, URL and body or, failing that, to the same method and URL: some
+//! bodies, like Instagram's signed one, differ from run to run.
+//!
+//! Cassettes keep requests as sent, numbers included, so recording is refused
+//! when `[storage] encrypt` or `[privacy] pseudonymize` is on.
+
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, Request, Response, StatusCode, Url, Version};
+use serde::{Deserialize, Serialize};
+
+use crate::http::{self, HttpError};
+
+/// Whether traffic is being recorded or replayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Record,
+    Replay,
+}
+
+/// Request or response body
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct Body {
+    /// The body as text, or in base64 when it is not UTF-8
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    body: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    base64: bool,
+}
+
+impl Body {
+    fn new(bytes: &[u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Self { body: text.to_owned(), base64: false },
+            Err(_) => Self { body: STANDARD.encode(bytes), base64: true },
+        }
+    }
+
+    fn bytes(&self) -> Result<Vec<u8>, base64::DecodeError> {
+        if self.base64 { STANDARD.decode(&self.body) } else { Ok(self.body.as_bytes().to_vec()) }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedRequest {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+    #[serde(flatten)]
+    body: Body,
+}
+
+impl RecordedRequest {
+    fn new(request: &Request) -> Self {
+        let body = request.body().and_then(reqwest::Body::as_bytes).unwrap_or_default();
+        Self {
+            method: request.method().to_string(),
+            url: request.url().to_string(),
+            headers: headers(request.headers()),
+            body: Body::new(body),
+        }
+    }
+
+    fn matches(&self, other: &Self) -> bool {
+        self.method == other.method && self.url == other.url
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    /// Final URL, after redirects
+    url: String,
+    headers: Vec<(String, String)>,
+    #[serde(flatten)]
+    body: Body,
+}
+
+impl RecordedResponse {
+    /// Turn the recording back into a response modules read like any other
+    fn to_response(&self) -> Result<Response, HttpError> {
+        let invalid = |e: &dyn std::fmt::Display| HttpError::Cassette(format!("invalid recorded response: {e}"));
+        let status = StatusCode::from_u16(self.status).map_err(|e| invalid(&e))?;
+        let url = Url::parse(&self.url).map_err(|e| invalid(&e))?;
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| invalid(&e))?;
+            headers.append(name, HeaderValue::from_str(value).map_err(|e| invalid(&e))?);
+        }
+        let body = self.body.bytes().map_err(|e| invalid(&e))?;
+        Ok(http::response(status, Version::HTTP_11, url, headers, body))
+    }
+}
+
+/// A request and the response it got
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+fn headers(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| (name.to_string(), String::from_utf8_lossy(value.as_bytes()).into_owned()))
+        .collect()
+}
+
+#[derive(Debug)]
+enum Tape {
+    /// Every exchange is appended to the file
+    Record(Mutex<File>),
+    /// Recorded exchanges, each answering a single request
+    Replay(Mutex<Vec<Option<Interaction>>>),
+}
+
+/// One module's recorded traffic
+#[derive(Debug)]
+pub struct Cassette {
+    tape: Tape,
+}
+
+impl Cassette {
+    /// Where `module`'s traffic is kept in `dir`
+    pub fn path(dir: &Path, module: &str) -> PathBuf {
+        let name: String = module
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+            .collect();
+        dir.join(format!("{name}.jsonl"))
+    }
+
+    /// Start recording `module`'s traffic, replacing an earlier recording, or load it for replay
+    pub fn open(dir: &Path, module: &str, mode: Mode) -> Result<Self> {
+        let path = Self::path(dir, module);
+        match mode {
+            Mode::Record => {
+                fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+                let file = File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
+                Ok(Self { tape: Tape::Record(Mutex::new(file)) })
+            }
+            Mode::Replay => {
+                // A module that sent nothing while recording has nothing to replay
+                if !path.exists() {
+                    return Ok(Self { tape: Tape::Replay(Mutex::default()) });
+                }
+                let file = File::open(&path).with_context(|| format!("failed to open {}", path.display()))?;
+                let interactions = BufReader::new(file)
+                    .lines()
+                    .enumerate()
+                    .filter(|(_, line)| line.as_ref().is_ok_and(|line| !line.trim().is_empty()))
+                    .map(|(index, line)| {
+                        let interaction = serde_json::from_str(&line?)
+                            .with_context(|| format!("{}:{}: invalid recording", path.display(), index + 1))?;
+                        Ok(Some(interaction))
+                    })
+                    .collect::<Result<_>>()?;
+                Ok(Self { tape: Tape::Replay(Mutex::new(interactions)) })
+            }
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        match self.tape {
+            Tape::Record(_) => Mode::Record,
+            Tape::Replay(_) => Mode::Replay,
+        }
+    }
+
+    /// The recorded response to `request`, used up by this call
+    pub fn play(&self, request: &Request) -> Result<Response, HttpError> {
+        let Tape::Replay(interactions) = &self.tape else {
+            unreachable!("only replay cassettes are played");
+        };
+        let wanted = RecordedRequest::new(request);
+        let mut interactions = interactions.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let unused = |exact: bool| {
+            interactions.iter().position(|interaction| {
+                interaction.as_ref().is_some_and(|interaction| {
+                    interaction.request.matches(&wanted) && (!exact || interaction.request.body == wanted.body)
+                })
+            })
+        };
+        let Some(index) = unused(true).or_else(|| unused(false)) else {
+            return Err(HttpError::Cassette(format!("no recorded response to {} {}", wanted.method, wanted.url)));
+        };
+        let interaction = interactions[index].take().expect("an unused interaction was found");
+        interaction.response.to_response()
+    }
+
+    /// Send `request`, record the exchange and hand the response back
+    pub async fn record(&self, client: &Client, request: Request) -> Result<Response, HttpError> {
+        let Tape::Record(file) = &self.tape else {
+            unreachable!("only record cassettes record");
+        };
+        let recorded = RecordedRequest::new(&request);
+        let response = client.execute(request).await?;
+        let status = response.status().as_u16();
+        let url = response.url().to_string();
+        let headers = headers(response.headers());
+        let body = response.bytes().await?;
+        let interaction = Interaction {
+            request: recorded,
+            response: RecordedResponse { status, url, headers, body: Body::new(&body) },
+        };
+
+        let line = serde_json::to_string(&interaction).map_err(|e| HttpError::Cassette(e.to_string()))?;
+        let mut file = file.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        writeln!(file, "{line}")
+            .and_then(|()| file.flush())
+            .map_err(|e| HttpError::Cassette(format!("failed to record the exchange: {e}")))?;
+        interaction.response.to_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HttpClient;
+
+    fn request(method: &str, url: &str, body: &str) -> Request {
+        reqwest::Client::new()
+            .request(method.parse().unwrap(), url)
+            .body(body.to_owned())
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_binary_bodies_round_trip() {
+        let text = Body::new(b"{\"status\":\"ok\"}");
+        assert!(!text.base64);
+        let binary = Body::new(&[0x1f, 0x8b, 0x08, 0xff]);
+        assert!(binary.base64);
+        assert_eq!(binary.bytes().unwrap(), [0x1f, 0x8b, 0x08, 0xff]);
+        assert_eq!(Cassette::path(Path::new("tapes"), "site/../x y"), Path::new("tapes/site_.._x_y.jsonl"));
+    }
+
+    #[test]
+    fn test_replay_prefers_matching_body() {
+        let interaction = |body: &str, answer: &str| {
+            Some(Interaction {
+                request: RecordedRequest::new(&request("POST", "https://example.com/check", body)),
+                response: RecordedResponse {
+                    status: 200,
+                    url: "https://example.com/check".to_owned(),
+                    headers: vec![("content-type".to_owned(), "text/plain".to_owned())],
+                    body: Body::new(answer.as_bytes()),
+                },
+            })
+        };
+        let interactions = vec![interaction("phone=1", "first"), interaction("phone=2", "second")];
+        let cassette = Cassette { tape: Tape::Replay(Mutex::new(interactions)) };
+
+        let second = cassette.play(&request("POST", "https://example.com/check", "phone=2")).unwrap();
+        assert_eq!(second.headers()["content-type"], "text/plain");
+        let other = cassette.play(&request("POST", "https://example.com/check", "phone=3")).unwrap();
+        assert_eq!(other.url().as_str(), "https://example.com/check");
+        assert!(cassette.play(&request("POST", "https://example.com/check", "phone=1")).is_err());
+        assert!(cassette.play(&request("GET", "https://example.com/", "")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay_offline() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/lookup")
+            .with_status(404)
+            .with_header("set-cookie", "xsrf_token=abc")
+            .with_body("{\"message\":\"No users found\"}")
+            .create_async()
+            .await;
+        let url = format!("{}/lookup", server.url());
+
+        let recorder = HttpClient::from(reqwest::Client::new()).with_cassette(Cassette::open(dir.path(), "instagram", Mode::Record).unwrap());
+        let live = recorder.read(recorder.execute(recorder.post(&url).body("q=1")).await.unwrap()).await.unwrap();
+        mock.assert_async().await;
+        drop(server);
+
+        let player = HttpClient::from(reqwest::Client::new()).with_cassette(Cassette::open(dir.path(), "instagram", Mode::Replay).unwrap());
+        let response = player.execute(player.post(&url).body("q=1")).await.unwrap();
+        assert_eq!(response.cookies().next().unwrap().value(), "abc");
+        let replayed = player.read(response).await.unwrap();
+
+        assert_eq!(replayed.status, live.status);
+        assert_eq!(replayed.url, live.url);
+        assert_eq!(replayed.body, live.body);
+        assert!(matches!(player.execute(player.post(&url)).await, Err(HttpError::Cassette(_))));
+    }
+}
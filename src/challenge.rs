@@ -0,0 +1,118 @@
+//! Challenge page detection
+//!
+//! Platforms that suspect automation often answer with a captcha, a bot-check
+//! interstitial or a login wall instead of the page a module expects. Reading
+//! such a page as "not found" would be a false negative, so modules look for
+//! one before interpreting a response and report the check as blocked. Nothing
+//! here tries to solve a challenge.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+/// Kind of challenge a platform served instead of an answer
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Challenge {
+    /// A captcha to solve (reCAPTCHA, hCaptcha, Amazon's image captcha, ...)
+    Captcha,
+    /// A bot-check interstitial (Cloudflare, Incapsula, ...)
+    BotCheck,
+    /// The platform wants a logged-in session or an account verification
+    LoginWall,
+    /// A challenge reported by a plugin without saying which kind
+    Other,
+}
+
+impl fmt::Display for Challenge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Captcha => "captcha",
+            Self::BotCheck => "bot check",
+            Self::LoginWall => "login wall",
+            Self::Other => "challenge",
+        })
+    }
+}
+
+/// Lowercase markers of each kind of challenge page
+const MARKERS: &[(Challenge, &[&str])] = &[
+    (
+        Challenge::Captcha,
+        &[
+            "/errors/validatecaptcha",
+            "captchacharacters",
+            "auth-captcha-image",
+            "g-recaptcha",
+            "recaptcha/api.js",
+            "hcaptcha.com/1/api.js",
+            "funcaptcha",
+            "arkoselabs.com",
+            "captcha-delivery.com",
+            "px-captcha",
+        ],
+    ),
+    (
+        Challenge::BotCheck,
+        &[
+            "challenges.cloudflare.com",
+            "cf-chl-",
+            "<title>just a moment...</title>",
+            "checking your browser before accessing",
+            "_incapsula_resource",
+            "<title>robot check</title>",
+            "to discuss automated access to amazon data",
+        ],
+    ),
+    (
+        Challenge::LoginWall,
+        &["\"challenge_required\"", "\"checkpoint_required\"", "\"login_required\""],
+    ),
+];
+
+/// Look for a challenge in a response body
+pub fn detect(body: &str) -> Option<Challenge> {
+    let body = body.to_ascii_lowercase();
+    MARKERS
+        .iter()
+        .find(|(_, markers)| markers.iter().any(|marker| body.contains(marker)))
+        .map(|(challenge, _)| *challenge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_captcha() {
+        let amazon = r#"<form method="get" action="/errors/validateCaptcha" name="">
+            <input autocomplete="off" type="text" id="captchacharacters" name="field-keywords">"#;
+        let recaptcha = r#"<div class="g-recaptcha" data-sitekey="abc"></div>"#;
+
+        assert_eq!(detect(amazon), Some(Challenge::Captcha));
+        assert_eq!(detect(recaptcha), Some(Challenge::Captcha));
+    }
+
+    #[test]
+    fn test_detect_bot_check() {
+        let cloudflare = r#"<html><head><title>Just a moment...</title></head>
+            <script src="/cdn-cgi/challenge-platform/h/b/orchestrate/chl_page/v1?ray=1"></script>
+            <div id="cf-chl-widget"></div></html>"#;
+
+        assert_eq!(detect(cloudflare), Some(Challenge::BotCheck));
+    }
+
+    #[test]
+    fn test_detect_login_wall() {
+        let instagram = r#"{"message": "checkpoint_required", "checkpoint_url": "/challenge/", "status": "fail"}"#;
+
+        assert_eq!(detect(instagram), Some(Challenge::LoginWall));
+    }
+
+    #[test]
+    fn test_detect_ordinary_pages() {
+        assert_eq!(detect(r#"<div id="auth-password-missing-alert">Enter your password</div>"#), None);
+        assert_eq!(detect(r#"{"message": "No users found", "status": "ok"}"#), None);
+        assert_eq!(detect(r#"{"status_code": "TAKEN_NUMBER"}"#), None);
+    }
+}
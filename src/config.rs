@@ -0,0 +1,662 @@
+//! Configuration file loading
+//!
+//! Settings that outlive a single invocation live in a TOML file, by default
+//! `~/.config/ignorant/config.toml`. A missing default file is not an error;
+//! a missing file passed with `--config` is.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+
+/// Top-level configuration file contents
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Site-definition bundle settings
+    pub sites: SitesConfig,
+    /// Checker plugin settings
+    pub plugins: PluginsConfig,
+    /// Retry policy for transient errors
+    pub retry: RetryConfig,
+    /// Per-domain request rate limits
+    pub rate_limit: RateLimitConfig,
+    /// Connection, request, module and run time limits
+    pub timeouts: TimeoutConfig,
+    /// Proxies requests are sent through
+    pub proxy: ProxyConfig,
+    /// Certificate authorities trusted for HTTPS
+    pub tls: TlsConfig,
+    /// Host resolution overrides
+    pub dns: DnsConfig,
+    /// Stand-in platforms served by `ignorant mock-server`
+    pub mock: MockConfig,
+    /// HTTP Archive written with `--har`
+    pub har: HarConfig,
+    /// When to stop running a module that keeps failing
+    pub circuit_breaker: CircuitBreakerConfig,
+    /// Who runs the tool and for which case, recorded in saved results
+    pub custody: CustodyConfig,
+    /// Where runs filed under a case are kept
+    pub history: HistoryConfig,
+    /// Which numbers may be checked
+    pub scope: ScopeConfig,
+    /// Log of every lookup performed
+    pub audit: AuditConfig,
+    /// How phone numbers are stored and printed
+    pub privacy: PrivacyConfig,
+    /// How long stored data is kept
+    pub retention: RetentionConfig,
+    /// Encryption of stored results and evidence
+    pub storage: StorageConfig,
+}
+
+/// `[sites]` section of the configuration file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SitesConfig {
+    /// Hex-encoded Ed25519 public keys allowed to sign site-definition bundles
+    pub trusted_keys: Vec<String>,
+    /// Bundles (directories or `.tar.gz` archives) loaded on every run
+    pub bundles: Vec<PathBuf>,
+}
+
+/// `[plugins]` section of the configuration file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PluginsConfig {
+    /// WebAssembly plugin settings
+    pub wasm: WasmConfig,
+    /// External-process plugins (`[[plugins.process]]`)
+    pub process: Vec<ProcessConfig>,
+}
+
+/// `[plugins.wasm]` section: where plugins live and the limits they run under
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WasmConfig {
+    /// Directory containing `<name>.wasm` plugins and their `<name>.toml` manifests
+    pub directory: Option<PathBuf>,
+    /// Instruction budget for a single check
+    pub fuel: u64,
+    /// Maximum linear memory per plugin instance, in MiB
+    pub max_memory_mb: usize,
+    /// Maximum HTTP requests a plugin may send per check
+    pub max_http_requests: u32,
+    /// Response bodies handed to plugins are truncated to this many bytes
+    pub max_response_bytes: usize,
+    /// Wall-clock limit for a single check, in seconds
+    pub timeout_secs: u64,
+}
+
+impl Default for WasmConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            fuel: 1_000_000_000,
+            max_memory_mb: 64,
+            max_http_requests: 8,
+            max_response_bytes: 1024 * 1024,
+            timeout_secs: 30,
+        }
+    }
+}
+
+/// A `[[plugins.process]]` entry: an executable speaking JSON over stdin/stdout
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProcessConfig {
+    /// Platform name (e.g. "example")
+    pub name: String,
+    /// Platform domain (e.g. "example.com")
+    pub domain: String,
+    /// Method used for checking (e.g. "login", "register", "other")
+    pub method: String,
+    /// Whether this platform frequently rate limits requests
+    #[serde(default)]
+    pub frequent_rate_limit: bool,
+    /// Plugin version, recorded in saved results
+    #[serde(default)]
+    pub version: Option<String>,
+    /// Executable to launch
+    pub command: PathBuf,
+    /// Arguments passed to the executable
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Free-form settings forwarded to the plugin as JSON
+    #[serde(default)]
+    pub settings: toml::Table,
+    /// Time allowed for the process to answer, in seconds
+    #[serde(default = "default_process_timeout")]
+    pub timeout_secs: u64,
+    /// Bytes of stderr kept for diagnostics; the rest is discarded
+    #[serde(default = "default_max_stderr_bytes")]
+    pub max_stderr_bytes: usize,
+}
+
+fn default_process_timeout() -> u64 {
+    30
+}
+
+fn default_max_stderr_bytes() -> usize {
+    16 * 1024
+}
+
+/// `[retry]` section: how transient errors are retried, with per-module overrides
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryConfig {
+    /// Attempts per check, including the first (1 disables retries)
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled after each attempt, in milliseconds
+    pub base_delay_ms: u64,
+    /// Upper bound on the delay between attempts, in milliseconds
+    pub max_delay_ms: u64,
+    /// Fraction of each delay that is randomized (0.0 = none, 1.0 = full jitter)
+    pub jitter: f64,
+    /// Overrides keyed by module name (`[retry.modules.amazon]`)
+    pub modules: BTreeMap<String, RetryOverride>,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay_ms: 500,
+            max_delay_ms: 5_000,
+            jitter: 1.0,
+            modules: BTreeMap::new(),
+        }
+    }
+}
+
+/// Per-module retry settings; unset fields fall back to `[retry]`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryOverride {
+    pub max_attempts: Option<u32>,
+    pub base_delay_ms: Option<u64>,
+    pub max_delay_ms: Option<u64>,
+    pub jitter: Option<f64>,
+}
+
+/// `[rate_limit]` section: token bucket applied to every request, per host
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Sustained requests per second to a single host (0 disables limiting)
+    pub requests_per_second: f64,
+    /// Requests that may be sent back to back before limiting kicks in
+    pub burst: u32,
+    /// Longest a `Retry-After` header may hold back a host, in seconds
+    pub max_pause_secs: u64,
+    /// Overrides keyed by host (`[rate_limit.domains."www.amazon.com"]`)
+    pub domains: BTreeMap<String, DomainRateLimit>,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            requests_per_second: 2.0,
+            burst: 4,
+            max_pause_secs: 60,
+            domains: BTreeMap::new(),
+        }
+    }
+}
+
+impl RateLimitConfig {
+    /// Rate and burst for `host`
+    pub fn limits_for(&self, host: &str) -> (f64, u32) {
+        let domain = self
+            .domains
+            .iter()
+            .find(|(domain, _)| domain.eq_ignore_ascii_case(host))
+            .map(|(_, limits)| limits);
+        (
+            domain.and_then(|d| d.requests_per_second).unwrap_or(self.requests_per_second),
+            domain.and_then(|d| d.burst).unwrap_or(self.burst),
+        )
+    }
+}
+
+/// Per-host rate limit; unset fields fall back to `[rate_limit]`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DomainRateLimit {
+    pub requests_per_second: Option<f64>,
+    pub burst: Option<u32>,
+}
+
+/// `[timeouts]` section: how long requests, checks and the whole run may take
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutConfig {
+    /// Time allowed to establish a connection, in seconds
+    pub connect_secs: u64,
+    /// Time allowed for a request, from sending it to reading the whole response, in seconds
+    pub read_secs: u64,
+    /// Time allowed for a whole check, retries included, in seconds (0 = unlimited)
+    pub module_secs: u64,
+    /// Time allowed for the whole run, in seconds (0 = unlimited)
+    pub deadline_secs: u64,
+    /// Overrides keyed by module name (`[timeouts.modules.amazon]`)
+    pub modules: BTreeMap<String, TimeoutOverride>,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            connect_secs: 10,
+            read_secs: 10,
+            module_secs: 0,
+            deadline_secs: 0,
+            modules: BTreeMap::new(),
+        }
+    }
+}
+
+/// Per-module time limits; unset fields fall back to `[timeouts]`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutOverride {
+    pub connect_secs: Option<u64>,
+    pub read_secs: Option<u64>,
+    pub module_secs: Option<u64>,
+}
+
+/// `[proxy]` section: where requests leave from
+///
+/// Without a URL here or in `--proxy`, the `HTTPS_PROXY` and `ALL_PROXY`
+/// environment variables are used, and `NO_PROXY` without a `no_proxy` list.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// Proxy URL (`http://`, `https://`, `socks5://` or `socks5h://`, credentials optional)
+    pub url: Option<String>,
+    /// Comma-separated hosts, domains, IP addresses and CIDR ranges reached directly
+    pub no_proxy: Option<String>,
+    /// Overrides keyed by module name (`[proxy.modules.amazon]`)
+    pub modules: BTreeMap<String, ProxyOverride>,
+}
+
+/// Per-module proxy; unset fields fall back to `[proxy]`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProxyOverride {
+    /// Proxy URL, or `direct` to send this module's requests without a proxy
+    pub url: Option<String>,
+    pub no_proxy: Option<String>,
+}
+
+/// `[tls]` section: which certificate authorities are trusted
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// Root store certificates are checked against (`--tls-roots`)
+    pub roots: TlsRoots,
+    /// PEM files of extra CA certificates to trust, such as a TLS-inspecting gateway's (`--ca-cert`)
+    pub ca_certs: Vec<PathBuf>,
+}
+
+/// Root certificate store
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum TlsRoots {
+    /// The operating system's store
+    #[default]
+    Native,
+    /// Mozilla's roots, built into the binary
+    Bundled,
+}
+
+/// `[dns]` section: where connections to some hosts go
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DnsConfig {
+    /// `HOST:PORT:ADDR` overrides, as with `--resolve`
+    pub resolve: Vec<String>,
+}
+
+/// `[har]` section: what is hidden in the HTTP Archive written with `--har`
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HarConfig {
+    /// Data replaced with `[redacted]`, on top of `--har-redact`
+    pub redact: Vec<HarRedaction>,
+}
+
+/// Data that can be left out of an HTTP Archive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum HarRedaction {
+    /// `Cookie` and `Set-Cookie` header values
+    Cookies,
+    /// The phone numbers checked, wherever they appear
+    Number,
+}
+
+/// `[mock]` section: what `ignorant mock-server` serves
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MockConfig {
+    /// Address to listen on (`--listen`, default 127.0.0.1:8443)
+    pub listen: Option<SocketAddr>,
+    /// Registered numbers, in full, and the platforms each is registered on
+    /// (`amazon`, an Amazon marketplace module such as `amazon_fr`, `instagram` or `snapchat`)
+    pub numbers: BTreeMap<String, Vec<String>>,
+}
+
+/// `[circuit_breaker]` section: skipping modules that keep failing during a batch
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CircuitBreakerConfig {
+    /// Consecutive errors, rate limits or challenges that open a module's breaker (0 disables it)
+    pub failure_threshold: u32,
+    /// How long an open breaker skips the module before a probe check, in seconds
+    pub cooldown_secs: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        Self {
+            failure_threshold: 5,
+            cooldown_secs: 60,
+        }
+    }
+}
+
+/// `[custody]` section: attribution recorded in every results file
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CustodyConfig {
+    /// Name of the person running the checks
+    pub operator: Option<String>,
+    /// Case the checks are run for
+    pub case_id: Option<String>,
+}
+
+/// `[history]` section: the store of runs filed under a case
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Directory holding one results file per run (default: `~/.local/share/ignorant/history`)
+    pub directory: Option<PathBuf>,
+}
+
+impl HistoryConfig {
+    /// Configured history directory, or the default one
+    pub fn directory(&self) -> Option<PathBuf> {
+        self.directory.clone().or_else(default_history_dir)
+    }
+}
+
+/// `[scope]` section: the numbers this installation is authorized to check
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScopeConfig {
+    /// Allowlist of numbers, prefixes and ranges with their authorization references
+    pub allowlist: Option<PathBuf>,
+    /// Enforce the allowlist on every run; `--enforce-scope` cannot be left off
+    pub enforce: bool,
+}
+
+/// `[audit]` section: the append-only log of lookups
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditConfig {
+    /// Audit log file; no log is kept when unset
+    pub log: Option<PathBuf>,
+    /// Log a keyed hash of each number (key in `IGNORANT_AUDIT_KEY`) instead of the number
+    pub hash_numbers: bool,
+}
+
+/// `[privacy]` section: keeping phone numbers out of stored data and output
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrivacyConfig {
+    /// Store a keyed hash of each number (key in `IGNORANT_PSEUDONYM_KEY`) instead of the number
+    pub pseudonymize: bool,
+    /// Mask the middle digits of numbers in printed output, as with `--mask`
+    pub mask_output: bool,
+}
+
+/// `[retention]` section: days each type of stored data is kept before `ignorant purge` removes it
+///
+/// Unset periods keep data forever.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Runs in the history
+    pub history_days: Option<u64>,
+    /// Evidence attached to stored results
+    pub evidence_days: Option<u64>,
+    /// Audit log entries
+    pub audit_days: Option<u64>,
+}
+
+/// `[storage]` section: encryption of the history and results files
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Refuse to store results unless they can be encrypted
+    pub encrypt: bool,
+    /// File whose contents are the secret the key is derived from, instead of a passphrase
+    pub keyfile: Option<PathBuf>,
+}
+
+impl Config {
+    /// Load the configuration from `path`, or from the default location when `None`
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::from_file(path),
+            None => match default_path() {
+                Some(path) if path.exists() => Self::from_file(&path),
+                _ => Ok(Self::default()),
+            },
+        }
+    }
+
+    fn from_file(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        let config: Self = toml::from_str(&contents)
+            .with_context(|| format!("failed to parse config file {}", path.display()))?;
+        config
+            .validate()
+            .with_context(|| format!("invalid config file {}", path.display()))?;
+        Ok(config)
+    }
+
+    /// Reject values TOML accepts but the tool cannot use
+    fn validate(&self) -> Result<()> {
+        if !self.retry.jitter.is_finite() {
+            bail!("[retry] jitter must be a number, got {}", self.retry.jitter);
+        }
+        for (module, retry) in &self.retry.modules {
+            if let Some(jitter) = retry.jitter
+                && !jitter.is_finite()
+            {
+                bail!("[retry.modules.{module}] jitter must be a number, got {jitter}");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Override for `module` in a `modules` table
+///
+/// Every Amazon marketplace (`amazon_fr`, `amazon_uk`, ...) falls back to the
+/// `amazon` entry, so overrides written before the marketplaces were split
+/// keep applying; a marketplace's own entry wins over it.
+pub fn module_override<'a, T>(modules: &'a BTreeMap<String, T>, module: &str) -> Option<&'a T> {
+    match modules.get(module) {
+        None if module.starts_with("amazon_") => modules.get("amazon"),
+        found => found,
+    }
+}
+
+/// Default configuration file location (`$XDG_CONFIG_HOME/ignorant/config.toml`)
+pub fn default_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("ignorant").join("config.toml"))
+}
+
+/// Default history location (`$XDG_DATA_HOME/ignorant/history`)
+pub fn default_history_dir() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local").join("share")))?;
+    Some(base.join("ignorant").join("history"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sites_section() {
+        let config: Config = toml::from_str(
+            r#"
+            [sites]
+            trusted_keys = ["00ff"]
+            bundles = ["/opt/team-sites"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.sites.trusted_keys, vec!["00ff".to_string()]);
+        assert_eq!(config.sites.bundles, vec![PathBuf::from("/opt/team-sites")]);
+    }
+
+    #[test]
+    fn test_empty_config() {
+        let config: Config = toml::from_str("").unwrap();
+
+        assert!(config.sites.trusted_keys.is_empty());
+        assert!(config.sites.bundles.is_empty());
+        assert_eq!(config.plugins.wasm.max_http_requests, 8);
+    }
+
+    #[test]
+    fn test_parse_wasm_section() {
+        let config: Config = toml::from_str(
+            r#"
+            [plugins.wasm]
+            directory = "/opt/ignorant/plugins"
+            fuel = 1000
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.plugins.wasm.directory, Some(PathBuf::from("/opt/ignorant/plugins")));
+        assert_eq!(config.plugins.wasm.fuel, 1000);
+        assert_eq!(config.plugins.wasm.timeout_secs, 30);
+    }
+
+    #[test]
+    fn test_parse_process_plugins() {
+        let config: Config = toml::from_str(
+            r#"
+            [[plugins.process]]
+            name = "example"
+            domain = "example.com"
+            method = "register"
+            command = "/opt/ignorant/bin/check-example"
+            settings = { region = "eu" }
+            "#,
+        )
+        .unwrap();
+
+        let plugin = &config.plugins.process[0];
+        assert_eq!(plugin.command, PathBuf::from("/opt/ignorant/bin/check-example"));
+        assert_eq!(plugin.settings["region"].as_str(), Some("eu"));
+        assert_eq!(plugin.timeout_secs, 30);
+        assert_eq!(plugin.max_stderr_bytes, 16 * 1024);
+    }
+
+    #[test]
+    fn test_parse_retry_overrides() {
+        let config: Config = toml::from_str(
+            r#"
+            [retry]
+            max_attempts = 2
+
+            [retry.modules.amazon]
+            max_attempts = 5
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.retry.max_attempts, 2);
+        assert_eq!(config.retry.base_delay_ms, 500);
+        assert_eq!(config.retry.modules["amazon"].max_attempts, Some(5));
+        assert_eq!(config.retry.modules["amazon"].jitter, None);
+    }
+
+    #[test]
+    fn test_parse_timeouts() {
+        let config: Config = toml::from_str(
+            r#"
+            [timeouts]
+            connect_secs = 3
+            deadline_secs = 60
+
+            [timeouts.modules.amazon]
+            read_secs = 20
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.timeouts.connect_secs, 3);
+        assert_eq!(config.timeouts.read_secs, 10);
+        assert_eq!(config.timeouts.deadline_secs, 60);
+        assert_eq!(config.timeouts.modules["amazon"].read_secs, Some(20));
+        assert_eq!(config.timeouts.modules["amazon"].connect_secs, None);
+    }
+
+    #[test]
+    fn test_amazon_override_fallback() {
+        let config: Config = toml::from_str(
+            r#"
+            [timeouts.modules.amazon]
+            read_secs = 20
+            [timeouts.modules.amazon_fr]
+            read_secs = 30
+            "#,
+        )
+        .unwrap();
+        let read_secs = |module| module_override(&config.timeouts.modules, module).and_then(|o| o.read_secs);
+
+        assert_eq!(read_secs("amazon"), Some(20));
+        assert_eq!(read_secs("amazon_uk"), Some(20));
+        assert_eq!(read_secs("amazon_fr"), Some(30));
+        assert_eq!(read_secs("instagram"), None);
+    }
+
+    #[test]
+    fn test_non_finite_jitter_rejected() {
+        for contents in ["[retry]\njitter = nan", "[retry.modules.instagram]\njitter = inf"] {
+            let file = tempfile::NamedTempFile::new().unwrap();
+            fs::write(file.path(), contents).unwrap();
+
+            let error = Config::load(Some(file.path())).unwrap_err();
+            assert!(format!("{error:#}").contains("jitter must be a number"), "{contents}");
+        }
+    }
+
+    #[test]
+    fn test_unknown_field_rejected() {
+        assert!(toml::from_str::<Config>("[sites]\ntrusted_key = []").is_err());
+    }
+
+    #[test]
+    fn test_missing_explicit_file() {
+        assert!(Config::load(Some(Path::new("/nonexistent/ignorant.toml"))).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+//! Chain of custody for saved results
+//!
+//! `--output` writes a results file that shows any edit made after the fact
+//! by someone without the custody key. The file is JSON lines: a `run` record
+//! holding the run metadata, one `result` record per check, and an `end`
+//! record with the number of results.
+//!
+//! Every record carries the hash of the record before it (`prev`) and its own
+//! `hash`, an HMAC-SHA256 over its other fields (keys sorted, compact JSON)
+//! keyed with `IGNORANT_CUSTODY_KEY`. Editing a record changes its hash, and
+//! removing, inserting or reordering records breaks the `prev` links. Without
+//! the key, neither can be covered up by hashing the records again, nor can a
+//! file cut short be given a new `end` record.
+//!
+//! The hash of the `end` record is the head of the chain. It is printed when
+//! the file is written and when it is verified, so it can be kept elsewhere
+//! (a case note, a ticket, a timestamping service): a file rewritten with the
+//! key no longer matches the head recorded at the time.
+//!
+//! With a [`Vault`] unlocked, the whole file is encrypted; the chain is checked
+//! after decryption.
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::CheckResult;
+use crate::vault::{self, Vault};
+
+/// `prev` of the first record
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Environment variable holding the key hash chains are computed with
+pub const KEY_ENV: &str = "IGNORANT_CUSTODY_KEY";
+
+/// Secret key of the hash chains
+#[derive(Clone)]
+pub struct ChainKey {
+    key: Vec<u8>,
+}
+
+impl ChainKey {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self { key: key.into() }
+    }
+
+    /// Key read from `IGNORANT_CUSTODY_KEY`
+    pub fn from_env() -> Result<Self> {
+        match std::env::var(KEY_ENV) {
+            Ok(key) if !key.is_empty() => Ok(Self::new(key)),
+            _ => bail!(
+                "{KEY_ENV} is not set; set it to a secret of your own and keep it, \
+                 since sealed files only verify under the key they were sealed with"
+            ),
+        }
+    }
+
+    /// HMAC of a record's fields, `prev` included and `hash` excluded
+    pub fn digest(&self, value: &Value) -> Result<String> {
+        // serde_json keeps object keys sorted, so this serialization is canonical
+        let bytes = serde_json::to_vec(value)?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(&bytes);
+        Ok(hex::encode(mac.finalize().into_bytes()))
+    }
+
+    /// Chain a record to `prev`: add its `prev` and `hash` fields, returning the hash
+    pub fn seal(&self, value: &mut Value, prev: String) -> Result<String> {
+        value["prev"] = Value::String(prev);
+        let hash = self.digest(value)?;
+        value["hash"] = Value::String(hash.clone());
+        Ok(hash)
+    }
+}
+
+/// Who ran what, where and when
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RunMetadata {
+    /// Unique identifier of the run
+    pub run_id: String,
+    /// Version of the tool
+    pub tool_version: String,
+    /// Version of every module that ran, by module name
+    pub modules: BTreeMap<String, String>,
+    /// Host the checks ran on
+    pub host: String,
+    /// Operator from the `[custody]` configuration
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operator: Option<String>,
+    /// Case from the `[custody]` configuration
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub case_id: Option<String>,
+    /// Authorization reference covering each number, with scope enforcement on
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub authorizations: BTreeMap<String, String>,
+    /// Free-text note given with `--note`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+}
+
+/// A line of a results file, without its chain fields
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Record {
+    Run(RunMetadata),
+    Result {
+        /// `+<country code> <phone>`, or its pseudonym (see [`crate::privacy`])
+        number: String,
+        result: CheckResult,
+    },
+    End {
+        results: usize,
+    },
+}
+
+/// Write a results file for a run, returning the head of its chain
+pub fn write(
+    path: &Path,
+    metadata: &RunMetadata,
+    results: &[(String, CheckResult)],
+    key: &ChainKey,
+    vault: Option<&Vault>,
+) -> Result<String> {
+    let records = std::iter::once(Record::Run(metadata.clone()))
+        .chain(results.iter().map(|(number, result)| Record::Result {
+            number: number.clone(),
+            result: result.clone(),
+        }))
+        .chain(std::iter::once(Record::End { results: results.len() }));
+
+    let mut contents = String::new();
+    let mut prev = GENESIS.to_owned();
+    for record in records {
+        let mut value = serde_json::to_value(&record)?;
+        prev = key.seal(&mut value, prev)?;
+        contents.push_str(&serde_json::to_string(&value)?);
+        contents.push('\n');
+    }
+
+    vault::write(path, &contents, vault).with_context(|| format!("failed to write results file {}", path.display()))?;
+    Ok(prev)
+}
+
+/// What a verified results file contains
+#[derive(Debug, Clone)]
+pub struct Verified {
+    pub metadata: RunMetadata,
+    /// Stored number and result of each check
+    pub results: Vec<(String, CheckResult)>,
+    /// Hash of the `end` record
+    pub head: String,
+}
+
+/// Check a results file for edits, returning its contents when it is intact
+pub fn verify(path: &Path, key: &ChainKey, vault: Option<&Vault>) -> Result<Verified> {
+    let contents = vault::read(path, vault).with_context(|| format!("failed to read results file {}", path.display()))?;
+
+    let mut prev = GENESIS.to_owned();
+    let mut metadata = None;
+    let mut results = Vec::new();
+    let mut ended = false;
+    for (index, line) in contents.lines().enumerate() {
+        let number = index + 1;
+        if ended {
+            bail!("line {number}: record after the end of the run");
+        }
+        let mut value: Value =
+            serde_json::from_str(line).with_context(|| format!("line {number}: not a JSON record"))?;
+        let Some(Value::String(hash)) = value.as_object_mut().and_then(|object| object.remove("hash")) else {
+            bail!("line {number}: record has no hash");
+        };
+        if value.get("prev").and_then(Value::as_str) != Some(prev.as_str()) {
+            bail!("line {number}: chain broken, a record was removed, inserted or reordered");
+        }
+        if key.digest(&value)? != hash {
+            bail!("line {number}: record was modified, or the file was sealed with another key");
+        }
+        prev = hash;
+
+        match serde_json::from_value(value).with_context(|| format!("line {number}: unknown record"))? {
+            Record::Run(run) if metadata.is_none() && number == 1 => metadata = Some(run),
+            Record::Run(_) => bail!("line {number}: unexpected run record"),
+            Record::Result { .. } | Record::End { .. } if metadata.is_none() => {
+                bail!("line {number}: results file must start with a run record")
+            }
+            Record::Result { number, result } => results.push((number, result)),
+            Record::End { results: count } if count == results.len() => ended = true,
+            Record::End { results: count } => {
+                bail!("line {number}: end record counts {count} results, found {}", results.len())
+            }
+        }
+    }
+
+    match metadata {
+        Some(metadata) if ended => Ok(Verified { metadata, results, head: prev }),
+        Some(_) => bail!("results file is truncated: no end record"),
+        None => bail!("results file is empty"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn metadata() -> RunMetadata {
+        RunMetadata {
+            run_id: "7d9f3c4e-0000-4000-8000-000000000000".to_owned(),
+            tool_version: "1.2.0".to_owned(),
+            modules: BTreeMap::from([("instagram".to_owned(), "1.2.0".to_owned())]),
+            host: "workstation".to_owned(),
+            operator: Some("J. Doe".to_owned()),
+            case_id: Some("2024-0042".to_owned()),
+            authorizations: BTreeMap::new(),
+            note: None,
+            started_at: Utc::now(),
+            finished_at: Utc::now(),
+        }
+    }
+
+    fn key() -> ChainKey {
+        ChainKey::new("custody secret")
+    }
+
+    fn write_sample(dir: &Path) -> std::path::PathBuf {
+        let path = dir.join("results.jsonl");
+        let results = vec![
+            ("+33 644637111".to_owned(), CheckResult::new("instagram", "instagram.com", "other").with_exists(true)),
+            ("+33 644637111".to_owned(), CheckResult::new("snapchat", "snapchat.com", "register")),
+        ];
+        write(&path, &metadata(), &results, &key(), None).unwrap();
+        path
+    }
+
+    fn edit_line(path: &Path, line: usize, edit: impl FnOnce(String) -> Option<String>) {
+        let contents = fs::read_to_string(path).unwrap();
+        let mut lines: Vec<String> = contents.lines().map(str::to_owned).collect();
+        match edit(lines[line].clone()) {
+            Some(edited) => lines[line] = edited,
+            None => {
+                lines.remove(line);
+            }
+        }
+        fs::write(path, lines.join("\n") + "\n").unwrap();
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_sample(dir.path());
+
+        let verified = verify(&path, &key(), None).unwrap();
+
+        assert_eq!(verified.metadata, metadata_without_time(&verified.metadata));
+        let last = fs::read_to_string(&path).unwrap().lines().last().unwrap().to_owned();
+        assert!(last.contains(&format!("\"hash\":\"{}\"", verified.head)));
+        assert_eq!(verified.results.len(), 2);
+        assert!(verified.results[0].1.exists);
+    }
+
+    fn metadata_without_time(verified: &RunMetadata) -> RunMetadata {
+        RunMetadata {
+            started_at: verified.started_at,
+            finished_at: verified.finished_at,
+            ..metadata()
+        }
+    }
+
+    #[test]
+    fn test_detects_modified_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_sample(dir.path());
+        edit_line(&path, 1, |line| Some(line.replace("\"exists\":true", "\"exists\":false")));
+
+        let err = verify(&path, &key(), None).unwrap_err();
+
+        assert!(err.to_string().contains("line 2: record was modified"), "{err}");
+    }
+
+    #[test]
+    fn test_detects_removed_record() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_sample(dir.path());
+        edit_line(&path, 1, |_| None);
+
+        let err = verify(&path, &key(), None).unwrap_err();
+
+        assert!(err.to_string().contains("line 2: chain broken"), "{err}");
+    }
+
+    #[test]
+    fn test_detects_truncation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_sample(dir.path());
+        edit_line(&path, 3, |_| None);
+
+        let err = verify(&path, &key(), None).unwrap_err();
+
+        assert!(err.to_string().contains("truncated"), "{err}");
+    }
+
+    #[test]
+    fn test_rehashed_edit_fails_without_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_sample(dir.path());
+        // Edit a result, then rebuild the whole chain the way an unkeyed one would be
+        let contents = fs::read_to_string(&path).unwrap().replace("\"exists\":true", "\"exists\":false");
+        let forger = ChainKey::new("guessed");
+        let mut prev = GENESIS.to_owned();
+        let mut forged = String::new();
+        for line in contents.lines() {
+            let mut value: Value = serde_json::from_str(line).unwrap();
+            value.as_object_mut().unwrap().remove("hash");
+            prev = forger.seal(&mut value, prev).unwrap();
+            forged.push_str(&serde_json::to_string(&value).unwrap());
+            forged.push('\n');
+        }
+        fs::write(&path, forged).unwrap();
+
+        let err = verify(&path, &key(), None).unwrap_err();
+
+        assert!(err.to_string().contains("line 1: record was modified"), "{err}");
+        assert!(verify(&path, &forger, None).is_ok());
+    }
+}
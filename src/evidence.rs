@@ -0,0 +1,176 @@
+//! Evidence backing a result
+//!
+//! With `--evidence`, modules attach the response their answer is based on:
+//! where it came from, its status and key headers, the fields the module read,
+//! a SHA-256 of the full body and a size-capped excerpt. The digest lets the
+//! excerpt be matched against a body saved elsewhere.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::http::Page;
+
+/// Largest body excerpt kept, in bytes
+pub const EXCERPT_BYTES: usize = 2048;
+
+/// Response headers worth keeping (lowercase)
+const KEY_HEADERS: &[&str] = &[
+    "content-type",
+    "date",
+    "server",
+    "location",
+    "retry-after",
+    "cf-ray",
+    "x-amz-rid",
+    "x-fb-trace-id",
+];
+
+/// The response a result is based on
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Evidence {
+    /// Final URL, after redirects
+    pub url: String,
+    /// HTTP status code
+    pub status: u16,
+    /// Key response headers
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    /// Response fields the module based its answer on
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+    /// Hex SHA-256 of the full body
+    pub body_sha256: String,
+    /// Size of the full body in bytes
+    pub body_bytes: usize,
+    /// Start of the body, at most [`EXCERPT_BYTES`] long
+    pub excerpt: String,
+    /// Whether the excerpt is shorter than the body
+    #[serde(default)]
+    pub truncated: bool,
+}
+
+impl Evidence {
+    /// Capture evidence from a response and the fields read from it
+    pub fn capture(page: &Page, fields: BTreeMap<String, String>) -> Self {
+        let headers = KEY_HEADERS
+            .iter()
+            .filter_map(|name| {
+                let value = page.headers.get(*name)?.to_str().ok()?;
+                Some(((*name).to_owned(), value.to_owned()))
+            })
+            .collect();
+        let excerpt = excerpt(&page.body, EXCERPT_BYTES);
+        Self {
+            url: page.url.to_string(),
+            status: page.status.as_u16(),
+            headers,
+            fields,
+            body_sha256: hex::encode(Sha256::digest(page.body.as_bytes())),
+            body_bytes: page.body.len(),
+            excerpt: excerpt.to_owned(),
+            truncated: excerpt.len() < page.body.len(),
+        }
+    }
+
+    /// Replace every occurrence of `secret` in the URL, fields and excerpt
+    ///
+    /// The body digest still covers the original body.
+    pub fn redact(&mut self, secret: &str, replacement: &str) {
+        self.url = self.url.replace(secret, replacement);
+        for value in self.fields.values_mut() {
+            *value = value.replace(secret, replacement);
+        }
+        self.excerpt = self.excerpt.replace(secret, replacement);
+    }
+}
+
+/// Longest prefix of `body` within `cap` bytes that ends on a character boundary
+fn excerpt(body: &str, cap: usize) -> &str {
+    let mut end = cap.min(body.len());
+    while !body.is_char_boundary(end) {
+        end -= 1;
+    }
+    &body[..end]
+}
+
+/// Top-level fields of a JSON body, rendered as strings; missing keys are left out
+pub fn json_fields(body: &str, keys: &[&str]) -> BTreeMap<String, String> {
+    let Ok(Value::Object(object)) = serde_json::from_str::<Value>(body) else {
+        return BTreeMap::new();
+    };
+    keys.iter()
+        .filter_map(|key| {
+            let value = match object.get(*key)? {
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            Some(((*key).to_owned(), value))
+        })
+        .collect()
+}
+
+/// Fields from `(name, value)` pairs
+pub fn fields<const N: usize>(pairs: [(&str, String); N]) -> BTreeMap<String, String> {
+    pairs.into_iter().map(|(name, value)| (name.to_owned(), value)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+    use reqwest::header::{CONTENT_TYPE, HeaderMap, HeaderValue, SET_COOKIE};
+
+    fn page(body: &str) -> Page {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(SET_COOKIE, HeaderValue::from_static("session=secret"));
+        Page {
+            url: "https://i.instagram.com/api/v1/users/lookup/".parse().unwrap(),
+            status: StatusCode::OK,
+            headers,
+            body: body.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_capture() {
+        let body = r#"{"message": "No users found", "status": "fail"}"#;
+
+        let evidence = Evidence::capture(&page(body), json_fields(body, &["message", "status", "error_type"]));
+
+        assert_eq!(evidence.status, 200);
+        assert_eq!(evidence.url, "https://i.instagram.com/api/v1/users/lookup/");
+        assert_eq!(evidence.headers["content-type"], "application/json");
+        assert!(!evidence.headers.contains_key("set-cookie"));
+        assert_eq!(evidence.fields["message"], "No users found");
+        assert!(!evidence.fields.contains_key("error_type"));
+        assert_eq!(evidence.body_sha256, hex::encode(Sha256::digest(body.as_bytes())));
+        assert_eq!(evidence.excerpt, body);
+        assert!(!evidence.truncated);
+    }
+
+    #[test]
+    fn test_excerpt_is_capped_on_char_boundary() {
+        let body = "é".repeat(EXCERPT_BYTES);
+
+        let evidence = Evidence::capture(&page(&body), BTreeMap::new());
+
+        assert!(evidence.truncated);
+        assert_eq!(evidence.excerpt.len(), EXCERPT_BYTES);
+        assert_eq!(evidence.body_bytes, body.len());
+        assert_eq!(excerpt("abcé", 4), "abc");
+    }
+
+    #[test]
+    fn test_json_fields() {
+        let fields = json_fields(r#"{"status_code": "OK", "spam": true}"#, &["status_code", "spam", "missing"]);
+
+        assert_eq!(fields["status_code"], "OK");
+        assert_eq!(fields["spam"], "true");
+        assert_eq!(fields.len(), 2);
+        assert!(json_fields("<html>", &["status_code"]).is_empty());
+    }
+}
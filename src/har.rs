@@ -0,0 +1,355 @@
+//! HTTP Archive export
+//!
+//! `--har FILE` writes every request modules send during a run, with its
+//! response and timings, as an HTTP Archive (HAR 1.2) that browser devtools
+//! open directly. A batch goes into a single archive. Each entry names the
+//! module that sent it in `_module`; a request that got no response has
+//! status 0 and the error in `_error`.
+//!
+//! With `--har-redact cookies` cookie headers are replaced with `[redacted]`;
+//! with `--har-redact number` so is every form of the numbers checked, in
+//! URLs, headers and bodies alike.
+
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use chrono::{DateTime, SecondsFormat, Utc};
+use reqwest::header::{CONTENT_TYPE, HeaderMap, LOCATION};
+use reqwest::{Request, Response, StatusCode, Version};
+use serde_json::{Value, json};
+
+use crate::VERSION;
+use crate::config::HarRedaction;
+use crate::http::{self, HttpError};
+
+const REDACTED: &str = "[redacted]";
+
+/// Entries of the archive being written, shared by every module's client
+#[derive(Debug)]
+pub struct Archive {
+    redact_cookies: bool,
+    /// Strings replaced wherever they appear, longest first
+    secrets: Vec<String>,
+    entries: Mutex<Vec<(SystemTime, Value)>>,
+}
+
+impl Archive {
+    /// Start an archive hiding `redactions`, with `numbers` the forms of every number checked
+    pub fn new(redactions: &[HarRedaction], numbers: impl IntoIterator<Item = String>) -> Self {
+        let mut secrets: Vec<String> = if redactions.contains(&HarRedaction::Number) {
+            numbers.into_iter().filter(|number| !number.is_empty()).collect()
+        } else {
+            Vec::new()
+        };
+        secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+        secrets.dedup();
+        Self {
+            redact_cookies: redactions.contains(&HarRedaction::Cookies),
+            secrets,
+            entries: Mutex::default(),
+        }
+    }
+
+    /// Capture the exchanges of `module` into this archive
+    pub fn capture(self: &Arc<Self>, module: &str) -> Capture {
+        Capture { archive: self.clone(), module: module.to_owned() }
+    }
+
+    fn push(&self, started: SystemTime, mut entry: Value) {
+        self.redact(&mut entry);
+        self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push((started, entry));
+    }
+
+    fn redact(&self, value: &mut Value) {
+        match value {
+            Value::String(text) => {
+                for secret in &self.secrets {
+                    if text.contains(secret.as_str()) {
+                        *text = text.replace(secret.as_str(), REDACTED);
+                    }
+                }
+            }
+            Value::Array(values) => values.iter_mut().for_each(|value| self.redact(value)),
+            Value::Object(object) => {
+                let is_cookie = object
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .is_some_and(|name| name.eq_ignore_ascii_case("cookie") || name.eq_ignore_ascii_case("set-cookie"));
+                if self.redact_cookies && is_cookie && object.contains_key("value") {
+                    object.insert("value".to_owned(), Value::from(REDACTED));
+                }
+                object.values_mut().for_each(|value| self.redact(value));
+            }
+            _ => {}
+        }
+    }
+
+    /// The archive as HAR 1.2 JSON, entries in the order requests were sent
+    pub fn to_json(&self) -> Value {
+        let mut entries = self.entries.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+        entries.sort_by_key(|(started, _)| *started);
+        json!({
+            "log": {
+                "version": "1.2",
+                "creator": { "name": "ignorant", "version": VERSION },
+                "pages": [],
+                "entries": entries.into_iter().map(|(_, entry)| entry).collect::<Vec<_>>(),
+            }
+        })
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let json = serde_json::to_string_pretty(&self.to_json())?;
+        fs::write(path, json + "\n").with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+/// One module's view of the archive
+#[derive(Debug)]
+pub struct Capture {
+    archive: Arc<Archive>,
+    module: String,
+}
+
+impl Capture {
+    /// Note a request as it is sent
+    pub fn start(&self, request: &Request) -> Pending<'_> {
+        Pending { capture: self, started: SystemTime::now(), clock: Instant::now(), request: request_json(request) }
+    }
+}
+
+/// A request waiting for its response
+pub struct Pending<'a> {
+    capture: &'a Capture,
+    started: SystemTime,
+    clock: Instant,
+    request: Value,
+}
+
+impl Pending<'_> {
+    /// Add the exchange to the archive and hand the response back, read in full
+    pub async fn finish(self, outcome: Result<Response, HttpError>) -> Result<Response, HttpError> {
+        let wait = millis(self.clock.elapsed());
+        let response = match outcome {
+            Ok(response) => response,
+            Err(e) => {
+                self.record(no_response(), wait, 0.0, Some(&e));
+                return Err(e);
+            }
+        };
+
+        let (status, version, url, headers) =
+            (response.status(), response.version(), response.url().clone(), response.headers().clone());
+        let body = response.bytes().await;
+        let receive = millis(self.clock.elapsed()) - wait;
+        match body {
+            Ok(body) => {
+                self.record(response_json(status, version, &headers, &body), wait, receive, None);
+                Ok(http::response(status, version, url, headers, body.to_vec()))
+            }
+            Err(e) => {
+                let e = HttpError::from(e);
+                self.record(response_json(status, version, &headers, &[]), wait, receive, Some(&e));
+                Err(e)
+            }
+        }
+    }
+
+    fn record(self, response: Value, wait: f64, receive: f64, error: Option<&HttpError>) {
+        let mut entry = json!({
+            "startedDateTime": DateTime::<Utc>::from(self.started).to_rfc3339_opts(SecondsFormat::Millis, true),
+            "time": wait + receive,
+            "request": self.request,
+            "response": response,
+            "cache": {},
+            "timings": { "blocked": -1, "dns": -1, "connect": -1, "ssl": -1, "send": 0, "wait": wait, "receive": receive },
+            "_module": self.capture.module,
+        });
+        if let Some(error) = error {
+            entry["_error"] = Value::from(error.to_string());
+        }
+        self.capture.archive.push(self.started, entry);
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn version(version: Version) -> String {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_2 => "HTTP/2",
+        Version::HTTP_3 => "HTTP/3",
+        _ => "HTTP/1.1",
+    }
+    .to_owned()
+}
+
+fn headers_json(headers: &HeaderMap) -> Vec<Value> {
+    headers
+        .iter()
+        .map(|(name, value)| json!({ "name": name.as_str(), "value": String::from_utf8_lossy(value.as_bytes()) }))
+        .collect()
+}
+
+fn mime_type(headers: &HeaderMap) -> String {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("x-unknown")
+        .to_owned()
+}
+
+fn request_json(request: &Request) -> Value {
+    let query: Vec<Value> = request
+        .url()
+        .query_pairs()
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect();
+    let body = request.body().and_then(reqwest::Body::as_bytes);
+    let mut entry = json!({
+        "method": request.method().as_str(),
+        "url": request.url().as_str(),
+        "httpVersion": version(request.version()),
+        "cookies": [],
+        "headers": headers_json(request.headers()),
+        "queryString": query,
+        "headersSize": -1,
+        "bodySize": body.map_or(0, <[u8]>::len),
+    });
+    if let Some(body) = body {
+        entry["postData"] = json!({
+            "mimeType": mime_type(request.headers()),
+            "text": String::from_utf8_lossy(body),
+        });
+    }
+    entry
+}
+
+/// Response of a request that got none
+fn no_response() -> Value {
+    json!({
+        "status": 0,
+        "statusText": "",
+        "httpVersion": "",
+        "cookies": [],
+        "headers": [],
+        "content": { "size": 0, "mimeType": "x-unknown" },
+        "redirectURL": "",
+        "headersSize": -1,
+        "bodySize": -1,
+    })
+}
+
+fn response_json(status: StatusCode, http_version: Version, headers: &HeaderMap, body: &[u8]) -> Value {
+    let mut content = json!({ "size": body.len(), "mimeType": mime_type(headers) });
+    match std::str::from_utf8(body) {
+        Ok(text) => content["text"] = Value::from(text),
+        Err(_) => {
+            content["text"] = Value::from(STANDARD.encode(body));
+            content["encoding"] = Value::from("base64");
+        }
+    }
+    json!({
+        "status": status.as_u16(),
+        "statusText": status.canonical_reason().unwrap_or_default(),
+        "httpVersion": version(http_version),
+        "cookies": [],
+        "headers": headers_json(headers),
+        "content": content,
+        "redirectURL": headers.get(LOCATION).and_then(|value| value.to_str().ok()).unwrap_or_default(),
+        "headersSize": -1,
+        "bodySize": body.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HttpClient;
+
+    fn entries(archive: &Archive) -> Vec<Value> {
+        archive.to_json()["log"]["entries"].as_array().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn test_exchanges_are_archived() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/lookup?source=default")
+            .with_status(404)
+            .with_header("content-type", "application/json")
+            .with_body("{\"message\":\"No users found\"}")
+            .create_async()
+            .await;
+        let archive = Arc::new(Archive::new(&[], []));
+        let client = HttpClient::from(reqwest::Client::new()).with_har(archive.capture("instagram"));
+
+        let request = client.post(format!("{}/lookup?source=default", server.url())).body("q=33644637111");
+        let page = client.read(client.execute(request).await.unwrap()).await.unwrap();
+        mock.assert_async().await;
+
+        // The module still reads the whole body
+        assert_eq!(page.body, "{\"message\":\"No users found\"}");
+        let entries = entries(&archive);
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry["_module"], "instagram");
+        assert_eq!(entry["request"]["method"], "POST");
+        assert_eq!(entry["request"]["queryString"][0]["value"], "default");
+        assert_eq!(entry["request"]["postData"]["text"], "q=33644637111");
+        assert_eq!(entry["response"]["status"], 404);
+        assert_eq!(entry["response"]["content"]["mimeType"], "application/json");
+        assert_eq!(entry["response"]["content"]["text"], page.body);
+        assert!(entry["timings"]["wait"].as_f64().unwrap() >= 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_failed_requests_are_archived() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        let archive = Arc::new(Archive::new(&[], []));
+        let client = HttpClient::from(reqwest::Client::new()).with_har(archive.capture("snapchat"));
+
+        assert!(client.execute(client.get(format!("http://127.0.0.1:{port}/"))).await.is_err());
+
+        let entries = entries(&archive);
+        assert_eq!(entries[0]["response"]["status"], 0);
+        assert!(entries[0]["_error"].as_str().unwrap().contains("error"));
+    }
+
+    #[test]
+    fn test_redaction() {
+        let numbers = ["+33644637111".to_owned(), "33644637111".to_owned(), "644637111".to_owned()];
+        let archive = Archive::new(&[HarRedaction::Cookies, HarRedaction::Number], numbers);
+        let entry = json!({
+            "request": {
+                "url": "https://example.com/?phone=%2B33644637111",
+                "headers": [{ "name": "Cookie", "value": "session=abc" }, { "name": "Accept", "value": "*/*" }],
+                "postData": { "text": "phone_number=644637111&country=FR" },
+            },
+            "response": { "headers": [{ "name": "set-cookie", "value": "xsrf_token=def" }] },
+        });
+
+        archive.push(SystemTime::now(), entry);
+
+        let entry = entries(&archive).remove(0);
+        let text = entry.to_string();
+        assert!(!text.contains("644637111") && !text.contains("abc") && !text.contains("def"), "{text}");
+        assert_eq!(entry["request"]["url"], "https://example.com/?phone=%2B[redacted]");
+        assert_eq!(entry["request"]["headers"][1]["value"], "*/*");
+        assert_eq!(entry["request"]["postData"]["text"], "phone_number=[redacted]&country=FR");
+
+        let kept = Archive::new(&[], ["644637111".to_owned()]);
+        kept.push(SystemTime::now(), json!({ "url": "https://example.com/644637111" }));
+        assert_eq!(entries(&kept)[0]["url"], "https://example.com/644637111");
+    }
+}
@@ -0,0 +1,263 @@
+//! History of runs filed under a case
+//!
+//! Every run with a case ID (`--case` or `[custody] case_id`) is saved to the
+//! history directory as a results file (see [`crate::custody`]), so it keeps
+//! its run metadata and stays tamper-evident. `ignorant cases` reads them back
+//! and summarises, for each case, the numbers checked and what the latest
+//! check of each number found.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+
+use crate::custody::{self, ChainKey, RunMetadata, Verified};
+use crate::vault::Vault;
+use crate::{CheckResult, Outcome};
+
+/// Saved runs that verified, with their files, and files that could not be read
+pub type Scan = (Vec<(PathBuf, Verified)>, Vec<(PathBuf, anyhow::Error)>);
+
+/// Directory of saved runs
+pub struct History {
+    directory: PathBuf,
+    key: ChainKey,
+    vault: Option<Arc<Vault>>,
+}
+
+impl History {
+    pub fn new(directory: PathBuf, key: ChainKey) -> Self {
+        Self { directory, key, vault: None }
+    }
+
+    /// Encrypt runs saved from now on, and decrypt encrypted ones when reading
+    pub fn with_vault(mut self, vault: Option<Arc<Vault>>) -> Self {
+        self.vault = vault;
+        self
+    }
+
+    pub fn vault(&self) -> Option<&Vault> {
+        self.vault.as_deref()
+    }
+
+    /// Key the runs are chained with
+    pub fn key(&self) -> &ChainKey {
+        &self.key
+    }
+
+    /// Save a run, returning the file it was written to
+    pub fn save(&self, metadata: &RunMetadata, results: &[(String, CheckResult)]) -> Result<PathBuf> {
+        fs::create_dir_all(&self.directory)
+            .with_context(|| format!("failed to create history directory {}", self.directory.display()))?;
+        let extension = if self.vault.is_some() { "jsonl.enc" } else { "jsonl" };
+        let name = format!("{}-{}.{extension}", metadata.started_at.format("%Y%m%dT%H%M%SZ"), metadata.run_id);
+        let path = self.directory.join(name);
+        custody::write(&path, metadata, results, &self.key, self.vault())?;
+        Ok(path)
+    }
+
+    /// Every saved run, oldest first
+    ///
+    /// A file that fails verification is reported and left out rather than
+    /// trusted.
+    pub fn runs(&self) -> Result<Vec<Verified>> {
+        Ok(self.files()?.into_iter().map(|(_, run)| run).collect())
+    }
+
+    /// Every saved run with the file it is stored in, oldest first
+    pub fn files(&self) -> Result<Vec<(PathBuf, Verified)>> {
+        let (runs, unreadable) = self.scan()?;
+        for (path, e) in unreadable {
+            eprintln!("Skipping {}: {e:#}", path.display());
+        }
+        Ok(runs)
+    }
+
+    /// Every saved run with the file it is stored in, oldest first, and the
+    /// files that could not be read or failed verification
+    pub fn scan(&self) -> Result<Scan> {
+        if !self.directory.exists() {
+            return Ok((Vec::new(), Vec::new()));
+        }
+        let mut paths: Vec<PathBuf> = fs::read_dir(&self.directory)
+            .with_context(|| format!("failed to read history directory {}", self.directory.display()))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<_>>()?;
+        paths.retain(|path| path.extension().is_some_and(|extension| extension == "jsonl" || extension == "enc"));
+
+        let mut runs = Vec::with_capacity(paths.len());
+        let mut unreadable = Vec::new();
+        for path in paths {
+            match custody::verify(&path, &self.key, self.vault()) {
+                Ok(run) => runs.push((path, run)),
+                Err(e) => unreadable.push((path, e)),
+            }
+        }
+        runs.sort_by_key(|(_, run)| run.metadata.started_at);
+        unreadable.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok((runs, unreadable))
+    }
+}
+
+/// Latest check of one number under a case
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exposure {
+    /// When the latest run checking this number started
+    pub checked_at: DateTime<Utc>,
+    /// Modules that found the number in that run
+    pub found: Vec<String>,
+    /// Modules that answered either way in that run
+    pub answered: usize,
+    /// Modules run on the number in that run
+    pub checked: usize,
+}
+
+/// Runs filed under one case
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Case {
+    pub runs: usize,
+    /// Operators who ran checks, in order of first appearance
+    pub operators: Vec<String>,
+    /// Notes given with `--note`, oldest first
+    pub notes: Vec<String>,
+    /// Latest exposure of each number, keyed by its stored form (number or pseudonym)
+    pub numbers: BTreeMap<String, Exposure>,
+}
+
+/// Group runs by case ID; runs without one are left out
+pub fn cases(runs: &[Verified]) -> BTreeMap<String, Case> {
+    let mut cases: BTreeMap<String, Case> = BTreeMap::new();
+    for run in runs {
+        let Some(case_id) = &run.metadata.case_id else {
+            continue;
+        };
+        let case = cases.entry(case_id.clone()).or_default();
+        case.runs += 1;
+        if let Some(operator) = &run.metadata.operator
+            && !case.operators.contains(operator)
+        {
+            case.operators.push(operator.clone());
+        }
+        case.notes.extend(run.metadata.note.clone());
+
+        let mut numbers: BTreeMap<String, Exposure> = BTreeMap::new();
+        for (number, result) in &run.results {
+            let exposure = numbers.entry(number.clone()).or_insert_with(|| Exposure {
+                checked_at: run.metadata.started_at,
+                found: Vec::new(),
+                answered: 0,
+                checked: 0,
+            });
+            exposure.checked += 1;
+            if matches!(result.outcome, Outcome::Found | Outcome::NotFound) {
+                exposure.answered += 1;
+            }
+            if result.outcome == Outcome::Found {
+                exposure.found.push(result.name.clone());
+            }
+        }
+        // Runs are oldest first, so later runs replace earlier summaries
+        case.numbers.extend(numbers);
+    }
+    cases
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(directory: PathBuf) -> History {
+        History::new(directory, ChainKey::new("custody secret"))
+    }
+
+    fn run(case_id: Option<&str>, operator: &str, results: &[(&str, Outcome)]) -> (RunMetadata, Vec<(String, CheckResult)>) {
+        let metadata = RunMetadata {
+            run_id: uuid::Uuid::new_v4().to_string(),
+            tool_version: "1.2.0".to_owned(),
+            modules: BTreeMap::new(),
+            host: "workstation".to_owned(),
+            operator: Some(operator.to_owned()),
+            case_id: case_id.map(str::to_owned),
+            authorizations: BTreeMap::new(),
+            note: Some(format!("run by {operator}")),
+            started_at: Utc::now(),
+            finished_at: Utc::now(),
+        };
+        let results = results
+            .iter()
+            .map(|(name, outcome)| {
+                let mut result = CheckResult::new(name, "example.com", "other");
+                result.outcome = *outcome;
+                ("+33 644637111".to_owned(), result)
+            })
+            .collect();
+        (metadata, results)
+    }
+
+    #[test]
+    fn test_save_and_read_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let directory = dir.path().join("history");
+        let history = history(directory.clone());
+        let (metadata, results) = run(Some("2024-0042"), "alice", &[("instagram", Outcome::Found)]);
+
+        let path = history.save(&metadata, &results).unwrap();
+        let runs = history.runs().unwrap();
+
+        assert!(path.starts_with(&directory));
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].metadata, metadata);
+    }
+
+    #[test]
+    fn test_missing_directory_is_empty() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(history(dir.path().join("none")).runs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_tampered_run_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = history(dir.path().to_owned());
+        let (metadata, results) = run(Some("2024-0042"), "alice", &[("instagram", Outcome::Found)]);
+        let path = history.save(&metadata, &results).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        fs::write(&path, contents.replace("\"found\"", "\"not_found\"")).unwrap();
+
+        assert!(history.runs().unwrap().is_empty());
+        let (_, unreadable) = history.scan().unwrap();
+        assert_eq!(unreadable[0].0, path);
+        assert!(format!("{:#}", unreadable[0].1).contains("record was modified"));
+    }
+
+    #[test]
+    fn test_cases_keep_latest_exposure() {
+        let (first, first_results) = run(Some("2024-0042"), "alice", &[("instagram", Outcome::Found)]);
+        let (second, second_results) = run(
+            Some("2024-0042"),
+            "bob",
+            &[("instagram", Outcome::Found), ("snapchat", Outcome::Found), ("amazon", Outcome::Error)],
+        );
+        let (other, other_results) = run(None, "carol", &[("instagram", Outcome::NotFound)]);
+        let runs: Vec<Verified> = [(first, first_results), (second, second_results), (other, other_results)]
+            .into_iter()
+            .map(|(metadata, results)| Verified { metadata, results, head: String::new() })
+            .collect();
+
+        let cases = cases(&runs);
+
+        assert_eq!(cases.len(), 1);
+        let case = &cases["2024-0042"];
+        assert_eq!(case.runs, 2);
+        assert_eq!(case.operators, ["alice", "bob"]);
+        assert_eq!(case.notes, ["run by alice", "run by bob"]);
+        let exposure = &case.numbers["+33 644637111"];
+        assert_eq!(exposure.found, ["instagram", "snapchat"]);
+        assert_eq!(exposure.answered, 2);
+        assert_eq!(exposure.checked, 3);
+    }
+}
@@ -0,0 +1,360 @@
+//! Shared HTTP client
+//!
+//! All modules send their requests through [`HttpClient`], which applies the
+//! per-domain rate limiter and classifies responses by status before the
+//! module looks at the body.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Client, IntoUrl, Method, Request, RequestBuilder, Response, ResponseBuilderExt, StatusCode, Url, Version};
+
+use crate::cassette::{Cassette, Mode};
+use crate::evidence::Evidence;
+use crate::har::Capture;
+use crate::proxy::Egress;
+use crate::ratelimit::RateLimiter;
+
+/// Why a request produced no usable response
+#[derive(Debug)]
+pub enum HttpError {
+    /// The request could not be sent or the response could not be read
+    Request(reqwest::Error),
+    /// The platform answered 429 or 503
+    RateLimited {
+        status: StatusCode,
+        retry_after: Option<Duration>,
+    },
+    /// The platform answered with another server error
+    Server(StatusCode),
+    /// The request has no recorded response, or its exchange could not be recorded
+    Cassette(String),
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Request(e) => write!(f, "{e}"),
+            Self::RateLimited { status, .. } => write!(f, "rate limited ({status})"),
+            Self::Server(status) => write!(f, "server error ({status})"),
+            Self::Cassette(message) => write!(f, "cassette: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl From<reqwest::Error> for HttpError {
+    fn from(error: reqwest::Error) -> Self {
+        Self::Request(error)
+    }
+}
+
+/// Parse a `Retry-After` header, given either in seconds or as an HTTP date
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// Response made of parts already read, which modules read like one from the network
+pub fn response(status: StatusCode, version: Version, url: Url, headers: HeaderMap, body: Vec<u8>) -> Response {
+    let mut response = http::Response::builder()
+        .status(status)
+        .version(version)
+        .url(url)
+        .body(body)
+        .expect("typed response parts are valid");
+    *response.headers_mut() = headers;
+    Response::from(response)
+}
+
+/// A response read in full
+#[derive(Debug, Clone)]
+pub struct Page {
+    /// Final URL, after redirects
+    pub url: Url,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+/// HTTP client shared by every module
+#[derive(Debug, Clone)]
+pub struct HttpClient {
+    client: Client,
+    limiter: Arc<RateLimiter>,
+    evidence: bool,
+    egress: Option<Arc<Egress>>,
+    /// Egress of each request sent since [`HttpClient::for_check`], in order, without repeats
+    routes: Arc<Mutex<Vec<String>>>,
+    cassette: Option<Arc<Cassette>>,
+    har: Option<Arc<Capture>>,
+}
+
+impl From<Client> for HttpClient {
+    /// Wrap `client` without any rate limiting
+    fn from(client: Client) -> Self {
+        Self::new(client, Arc::new(RateLimiter::unlimited()))
+    }
+}
+
+impl HttpClient {
+    /// Wrap `client`; clients sharing `limiter` share its per-host budgets
+    pub fn new(client: Client, limiter: Arc<RateLimiter>) -> Self {
+        Self {
+            client,
+            limiter,
+            evidence: false,
+            egress: None,
+            routes: Arc::default(),
+            cassette: None,
+            har: None,
+        }
+    }
+
+    /// Have modules attach the response behind each answer to their results
+    pub fn with_evidence(mut self, enabled: bool) -> Self {
+        self.evidence = enabled;
+        self
+    }
+
+    /// Record the egress `client` was built with, for results to report
+    pub fn with_egress(mut self, egress: Egress) -> Self {
+        self.egress = Some(Arc::new(egress));
+        self
+    }
+
+    /// Copy of this client that tracks the egress of its own requests only
+    pub fn for_check(&self) -> Self {
+        Self {
+            routes: Arc::default(),
+            ..self.clone()
+        }
+    }
+
+    /// How the requests sent since [`HttpClient::for_check`] left, when the egress is known
+    ///
+    /// Each request is routed by its own host, so a `no_proxy` entry naming a
+    /// subdomain shows up here; a check whose requests left in different ways
+    /// lists each of them.
+    pub fn egress_used(&self) -> Option<String> {
+        let routes = self.routes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        (!routes.is_empty()).then(|| routes.join(", "))
+    }
+
+    /// Record every exchange to `cassette`, or answer every request from it
+    pub fn with_cassette(mut self, cassette: Cassette) -> Self {
+        self.cassette = Some(Arc::new(cassette));
+        self
+    }
+
+    /// Add every exchange to an HTTP Archive
+    pub fn with_har(mut self, capture: Capture) -> Self {
+        self.har = Some(Arc::new(capture));
+        self
+    }
+
+    pub fn get(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client.get(url)
+    }
+
+    pub fn post(&self, url: impl IntoUrl) -> RequestBuilder {
+        self.client.post(url)
+    }
+
+    pub fn request(&self, method: Method, url: impl IntoUrl) -> RequestBuilder {
+        self.client.request(method, url)
+    }
+
+    /// Send a request once the rate limiter allows it, whatever the response status
+    ///
+    /// A `Retry-After` on a 429 or 503 response pauses further requests to that host.
+    /// A replay cassette answers without sending anything.
+    pub async fn execute(&self, request: RequestBuilder) -> Result<Response, HttpError> {
+        let request = request.build()?;
+        match &self.har {
+            Some(capture) => {
+                let pending = capture.start(&request);
+                pending.finish(self.exchange(request).await).await
+            }
+            None => self.exchange(request).await,
+        }
+    }
+
+    async fn exchange(&self, request: Request) -> Result<Response, HttpError> {
+        if let Some(cassette) = &self.cassette
+            && cassette.mode() == Mode::Replay
+        {
+            return cassette.play(&request);
+        }
+        let host = request.url().host_str().unwrap_or_default().to_owned();
+        if let Some(egress) = &self.egress {
+            let route = egress.label(&host);
+            let mut routes = self.routes.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if !routes.contains(&route) {
+                routes.push(route);
+            }
+        }
+        self.limiter.acquire(&host).await;
+
+        let response = match &self.cassette {
+            Some(cassette) => cassette.record(&self.client, request).await?,
+            None => self.client.execute(request).await?,
+        };
+        if matches!(response.status(), StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE)
+            && let Some(wait) = retry_after(response.headers())
+        {
+            self.limiter.pause(&host, wait);
+        }
+        Ok(response)
+    }
+
+    /// Send a request, treating rate-limit and server-error statuses as failures
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, HttpError> {
+        let response = self.execute(request).await?;
+        match response.status() {
+            status @ (StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE) => Err(HttpError::RateLimited {
+                status,
+                retry_after: retry_after(response.headers()),
+            }),
+            status if status.is_server_error() => Err(HttpError::Server(status)),
+            _ => Ok(response),
+        }
+    }
+
+    /// Read a response body, keeping what evidence needs from the response
+    pub async fn read(&self, response: Response) -> reqwest::Result<Page> {
+        let url = response.url().clone();
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.text().await?;
+        Ok(Page { url, status, headers, body })
+    }
+
+    /// Evidence for an answer based on `page`, when evidence is enabled
+    pub fn evidence(&self, page: &Page, fields: BTreeMap<String, String>) -> Option<Evidence> {
+        self.evidence.then(|| Evidence::capture(page, fields))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_retry_after_seconds() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("120"));
+
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(120)));
+    }
+
+    #[test]
+    fn test_retry_after_http_date() {
+        let mut headers = HeaderMap::new();
+        let date = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(30));
+        headers.insert(RETRY_AFTER, HeaderValue::from_str(&date).unwrap());
+
+        let wait = retry_after(&headers).unwrap();
+        assert!(wait > Duration::from_secs(25) && wait <= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_retry_after_in_the_past_or_invalid() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("soon"));
+        assert_eq!(retry_after(&headers), None);
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+
+    #[tokio::test]
+    async fn test_send_classifies_status() {
+        let mut server = mockito::Server::new_async().await;
+        let limited = server
+            .mock("GET", "/limited")
+            .with_status(429)
+            .with_header("Retry-After", "0")
+            .create_async()
+            .await;
+        let unavailable = server.mock("GET", "/down").with_status(503).create_async().await;
+        let broken = server.mock("GET", "/broken").with_status(500).create_async().await;
+        let ok = server.mock("GET", "/ok").with_status(404).create_async().await;
+        let client = HttpClient::from(Client::new());
+
+        let result = client.send(client.get(format!("{}/limited", server.url()))).await;
+        assert!(matches!(
+            result,
+            Err(HttpError::RateLimited { retry_after: Some(wait), .. }) if wait.is_zero()
+        ));
+        let result = client.send(client.get(format!("{}/down", server.url()))).await;
+        assert!(matches!(result, Err(HttpError::RateLimited { retry_after: None, .. })));
+        let result = client.send(client.get(format!("{}/broken", server.url()))).await;
+        assert!(matches!(result, Err(HttpError::Server(StatusCode::INTERNAL_SERVER_ERROR))));
+        let result = client.send(client.get(format!("{}/ok", server.url()))).await;
+        assert_eq!(result.unwrap().status(), StatusCode::NOT_FOUND);
+
+        for mock in [limited, unavailable, broken, ok] {
+            mock.assert_async().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_undecodable_response_is_unexpected() {
+        let url = Url::parse("https://example.com/lookup").unwrap();
+        let response = response(StatusCode::OK, Version::HTTP_11, url, HeaderMap::new(), b"<html>".to_vec());
+        let error = response.json::<serde_json::Value>().await.unwrap_err();
+        assert!(error.is_decode());
+
+        let result = crate::CheckResult::new("example", "example.com", "other").with_request_error(&error);
+
+        assert_eq!(result.outcome, crate::Outcome::Unexpected);
+        assert!(!result.rate_limit);
+        assert!(result.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_egress_follows_request_host() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server.mock("GET", "/").expect(2).create_async().await;
+        let config: crate::config::Config = toml::from_str(
+            r#"
+            [proxy]
+            url = "http://127.0.0.1:9"
+            no_proxy = "accounts.example.com"
+            "#,
+        )
+        .unwrap();
+        let egress = Egress::for_module(&config.proxy, "example").unwrap();
+        let client = Client::builder()
+            .proxy(egress.proxy().unwrap())
+            .resolve("accounts.example.com", server.socket_address())
+            .build()
+            .unwrap();
+        let client = HttpClient::from(client).with_egress(egress.clone());
+        let url = format!("http://accounts.example.com:{}/", server.socket_address().port());
+
+        let check = client.for_check();
+        check.send(check.get(&url)).await.unwrap();
+        // Only the domain named in the result would have gone through the proxy
+        assert_eq!(egress.label("example.com"), "http://127.0.0.1:9");
+        assert_eq!(check.egress_used().as_deref(), Some("direct"));
+
+        let other = client.for_check();
+        assert_eq!(other.egress_used(), None);
+        other.send(other.get(&url)).await.unwrap();
+        let _ = other.send(other.get("http://example.com/")).await;
+        assert_eq!(other.egress_used().as_deref(), Some("direct, http://127.0.0.1:9"));
+        mock.assert_async().await;
+    }
+}
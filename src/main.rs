@@ -20,20 +20,104 @@
 //! - **Rate limit detection** and handling
 //! - **Cross-platform** native binary
 
-use std::time::Instant;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand};
 use colored::Colorize;
 use indicatif::{ProgressBar, ProgressStyle};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use tokio::task::JoinSet;
 
+mod audit;
+mod batch;
+mod breaker;
+mod cassette;
+mod challenge;
+mod config;
+mod custody;
+mod evidence;
+mod har;
+mod history;
+mod http;
+mod mock;
 mod modules;
+mod plugins;
+mod privacy;
+mod proxy;
+mod ratelimit;
+mod resolve;
+mod retention;
+mod retry;
+mod scope;
+mod sites;
+mod timeouts;
+mod tls;
 mod user_agents;
+mod vault;
 
-use modules::{amazon, instagram, snapchat};
+use audit::AuditLog;
+use breaker::CircuitBreaker;
+use cassette::Cassette;
+use challenge::Challenge;
+use config::Config;
+use evidence::Evidence;
+use history::History;
+use http::{HttpClient, HttpError};
+use modules::Module;
+use plugins::wasm::{self, WasmPlugin};
+use privacy::Pseudonymizer;
+use proxy::Egress;
+use ratelimit::RateLimiter;
+use retry::RetryPolicy;
+use scope::Scope;
+use sites::bundle;
+use timeouts::Timeouts;
+use tls::Trust;
+use vault::Vault;
+
+/// Version of the tool, recorded in saved results
+pub const VERSION: &str = "1.2.0";
+
+/// What a check concluded
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// The phone number is registered on the platform
+    Found,
+    /// The phone number is not registered on the platform
+    #[default]
+    NotFound,
+    /// The platform refused to answer
+    RateLimited,
+    /// The platform served a challenge (captcha, bot check, login wall) instead of an answer
+    Blocked,
+    /// The platform rejected the phone number itself (invalid, unsupported country, ...)
+    InvalidInput,
+    /// The request failed before the platform answered (connection reset, DNS, ...)
+    Error,
+    /// The platform answered, but not in a way the module recognises (unknown status, changed body)
+    Unexpected,
+    /// The platform's certificate was not trusted (unknown root, TLS-inspecting gateway, ...)
+    TlsError,
+    /// The check ran out of time (module timeout or run deadline) and was cancelled
+    TimedOut,
+    /// The check was not run because the module's circuit breaker is open
+    Skipped,
+}
+
+impl Outcome {
+    /// Whether trying again could produce a different answer
+    pub fn is_transient(self) -> bool {
+        matches!(self, Self::Error)
+    }
+}
 
 /// Result of checking a phone number on a specific platform
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -50,6 +134,33 @@ pub struct CheckResult {
     pub rate_limit: bool,
     /// Whether the phone number exists on this platform
     pub exists: bool,
+    /// What the check concluded
+    #[serde(default)]
+    pub outcome: Outcome,
+    /// Why the check failed, when `outcome` is `invalid_input`, `error`, `unexpected`, `tls_error` or `timed_out`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Number of attempts made, including retries
+    #[serde(default)]
+    pub attempts: u32,
+    /// Challenge the platform served, when `outcome` is `blocked`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub challenge: Option<Challenge>,
+    /// Seconds the platform asked to wait before trying again (`Retry-After`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+    /// Version of the site-definition bundle the check came from, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle_version: Option<String>,
+    /// Response the answer is based on, with `--evidence`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub evidence: Option<Evidence>,
+    /// When the check finished (UTC)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checked_at: Option<DateTime<Utc>>,
+    /// How the check's requests left: `direct`, or the proxy URL without its credentials
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub egress: Option<String>,
 }
 
 impl CheckResult {
@@ -62,48 +173,339 @@ impl CheckResult {
             frequent_rate_limit: false,
             rate_limit: false,
             exists: false,
+            outcome: Outcome::NotFound,
+            error: None,
+            attempts: 1,
+            challenge: None,
+            retry_after: None,
+            bundle_version: None,
+            evidence: None,
+            checked_at: None,
+            egress: None,
         }
     }
 
     /// Mark this result as rate limited
     fn with_rate_limit(mut self) -> Self {
         self.rate_limit = true;
+        self.outcome = Outcome::RateLimited;
         self
     }
 
     /// Set whether the phone number exists on this platform
     fn with_exists(mut self, exists: bool) -> Self {
         self.exists = exists;
+        self.outcome = if exists { Outcome::Found } else { Outcome::NotFound };
+        self
+    }
+
+    /// Mark this result as blocked by a challenge page, leaving the answer unknown
+    fn with_challenge(mut self, challenge: Challenge) -> Self {
+        self.outcome = Outcome::Blocked;
+        self.challenge = Some(challenge);
+        self
+    }
+
+    /// Mark this result as rejected by the platform because of the number itself
+    fn with_invalid_input(mut self, reason: impl Into<String>) -> Self {
+        self.outcome = Outcome::InvalidInput;
+        self.error = Some(reason.into());
+        self
+    }
+
+    /// Mark this result as failed before the platform answered
+    fn with_error(mut self, error: impl Into<String>) -> Self {
+        self.outcome = Outcome::Error;
+        self.error = Some(error.into());
+        self
+    }
+
+    /// Mark this result as answered in a way the module does not recognise
+    fn with_unexpected(mut self, reason: impl Into<String>) -> Self {
+        self.outcome = Outcome::Unexpected;
+        self.error = Some(reason.into());
+        self
+    }
+
+    /// Mark this result as failed because the platform's certificate was not trusted
+    fn with_tls_error(mut self, error: impl Into<String>) -> Self {
+        self.outcome = Outcome::TlsError;
+        self.error = Some(error.into());
+        self
+    }
+
+    /// Mark this result as cancelled for running out of time
+    fn with_timeout(mut self, reason: impl Into<String>) -> Self {
+        self.outcome = Outcome::TimedOut;
+        self.error = Some(reason.into());
+        self
+    }
+
+    /// Mark this result as skipped by an open circuit breaker
+    fn with_skipped(mut self) -> Self {
+        self.outcome = Outcome::Skipped;
+        self.attempts = 0;
+        self
+    }
+
+    /// Record a failed request: a response that could not be decoded means the
+    /// platform answered with something unexpected, a rejected certificate is a
+    /// TLS error and anything else is a transport error
+    fn with_request_error(self, error: &reqwest::Error) -> Self {
+        if tls::is_certificate_error(error) {
+            self.with_tls_error(error.to_string())
+        } else if error.is_decode() {
+            self.with_unexpected(error.to_string())
+        } else {
+            self.with_error(error.to_string())
+        }
+    }
+
+    /// Record a failed request, keeping any `Retry-After` the platform sent
+    fn with_http_error(self, error: &HttpError) -> Self {
+        match error {
+            HttpError::Request(e) => self.with_request_error(e),
+            HttpError::RateLimited { retry_after, .. } => {
+                let mut result = self.with_rate_limit();
+                result.retry_after = retry_after.map(|wait| wait.as_secs());
+                result
+            }
+            HttpError::Server(_) | HttpError::Cassette(_) => self.with_error(error.to_string()),
+        }
+    }
+
+    /// Attach the response this result is based on
+    fn with_evidence(mut self, evidence: Option<Evidence>) -> Self {
+        self.evidence = evidence;
+        self
+    }
+
+    /// Record when the check finished
+    fn with_checked_at(mut self, at: DateTime<Utc>) -> Self {
+        self.checked_at = Some(at);
+        self
+    }
+
+    /// Record the version of the bundle this check was defined in
+    fn with_bundle_version(mut self, version: &str) -> Self {
+        self.bundle_version = Some(version.to_owned());
         self
     }
 }
 
+/// Results for one number, as printed with `--json`
+#[derive(Debug, Serialize)]
+struct NumberResults<'a> {
+    country_code: &'a str,
+    phone: String,
+    results: &'a [CheckResult],
+}
+
 #[derive(Parser, Debug)]
 #[command(name = "ignorant")]
 #[command(about = "Check if a phone number is used on different sites")]
-#[command(version = "1.2.0")]
+#[command(version = VERSION)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+#[command(after_help = "\
+Environment:
+  IGNORANT_CUSTODY_KEY    Secret saved results, the case history and the audit log are sealed with.
+                          Needed by --output, --case, [audit] log, verify, cases, purge and audit verify;
+                          files only verify under the key they were sealed with, so keep it.
+  IGNORANT_PSEUDONYM_KEY  Key numbers are hashed with when [privacy] pseudonymize is on
+  IGNORANT_AUDIT_KEY      Key audit log numbers are hashed with when [audit] hash_numbers is on
+  IGNORANT_PASSPHRASE     Passphrase stored results are encrypted with when [storage] encrypt is on")]
 pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Country code of the phone (Example: 33)
-    pub country_code: String,
+    #[arg(required_unless_present = "batch")]
+    pub country_code: Option<String>,
     
     /// Target phone number (Example: 644637111)
-    pub phone: String,
+    #[arg(required_unless_present = "batch")]
+    pub phone: Option<String>,
+
+    /// Check every number in FILE, one `<COUNTRY_CODE> <PHONE>` per line
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["country_code", "phone"])]
+    pub batch: Option<PathBuf>,
     
+    /// Hide the middle digits of phone numbers in the output (`+33 6•••••111`)
+    #[arg(long, global = true, action = clap::ArgAction::SetTrue)]
+    pub mask: bool,
+
     /// Display only the sites used by the target phone number
     #[arg(long, action = clap::ArgAction::SetTrue)]
     pub only_used: bool,
     
     /// Don't color terminal output
-    #[arg(long, action = clap::ArgAction::SetTrue)]
+    #[arg(long, global = true, action = clap::ArgAction::SetTrue)]
     pub no_color: bool,
     
     /// Do not clear the terminal to display the results
     #[arg(long, action = clap::ArgAction::SetTrue)]
     pub no_clear: bool,
+
+    /// Print the results as JSON instead of text
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub json: bool,
+
+    /// Also save the results to FILE, hash-chained with the run metadata (see `ignorant verify`; needs IGNORANT_CUSTODY_KEY)
+    #[arg(long, value_name = "FILE")]
+    pub output: Option<PathBuf>,
+
+    /// File the run under this case in the history store (overrides `[custody] case_id`; needs IGNORANT_CUSTODY_KEY)
+    #[arg(long = "case", value_name = "ID")]
+    pub case_id: Option<String>,
+
+    /// Note recorded with the run
+    #[arg(long, value_name = "TEXT")]
+    pub note: Option<String>,
+
+    /// Person running the checks (overrides `[custody] operator`)
+    #[arg(long, value_name = "NAME")]
+    pub operator: Option<String>,
+
+    /// Refuse numbers that are not in the `[scope]` allowlist (always on with `[scope] enforce = true`)
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub enforce_scope: bool,
+
+    /// Attach the response behind each answer (URL, status, headers, body digest and excerpt)
+    #[arg(long, action = clap::ArgAction::SetTrue)]
+    pub evidence: bool,
     
-    /// Set max timeout value in seconds (default: 10)
-    #[arg(short = 'T', long, default_value = "10")]
-    pub timeout: u64,
+    /// Set max time for each request in seconds (default: 10)
+    #[arg(short = 'T', long)]
+    pub timeout: Option<u64>,
+
+    /// Send requests through this proxy (http://, https://, socks5:// or socks5h://, credentials optional)
+    #[arg(long, value_name = "URL")]
+    pub proxy: Option<String>,
+
+    /// Also trust the CA certificates in this PEM file (repeatable)
+    #[arg(long = "ca-cert", value_name = "FILE")]
+    pub ca_certs: Vec<PathBuf>,
+
+    /// Check certificates against the system's root store or the one built in
+    #[arg(long, value_name = "STORE")]
+    pub tls_roots: Option<config::TlsRoots>,
+
+    /// Connect to ADDR for requests to HOST on PORT, like curl (repeatable)
+    #[arg(long, value_name = "HOST:PORT:ADDR")]
+    pub resolve: Vec<String>,
+
+    /// Set max time to establish a connection in seconds (default: 10)
+    #[arg(long, value_name = "SECS")]
+    pub connect_timeout: Option<u64>,
+
+    /// Save every request and response to DIR, one cassette per module
+    #[arg(long, value_name = "DIR", conflicts_with = "replay")]
+    pub record: Option<PathBuf>,
+
+    /// Answer requests from the cassettes in DIR instead of the network
+    #[arg(long, value_name = "DIR")]
+    pub replay: Option<PathBuf>,
+
+    /// Write every request and response of the run to FILE as an HTTP Archive
+    #[arg(long, value_name = "FILE")]
+    pub har: Option<PathBuf>,
+
+    /// Hide cookies or the numbers checked in the HTTP Archive (repeatable)
+    #[arg(long, value_name = "WHAT", requires = "har")]
+    pub har_redact: Vec<config::HarRedaction>,
+
+    /// Stop after this many seconds, reporting unfinished checks as timed out
+    #[arg(long, value_name = "SECS")]
+    pub deadline: Option<u64>,
+
+    /// Load site definitions from a signed bundle (directory or .tar.gz)
+    #[arg(long = "sites", value_name = "BUNDLE")]
+    pub sites: Vec<PathBuf>,
+
+    /// Load WebAssembly plugins from this directory instead of the configured one
+    #[arg(long, value_name = "DIR")]
+    pub plugin_dir: Option<PathBuf>,
+
+    /// Path to the configuration file (default: ~/.config/ignorant/config.toml)
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// List the cases in the history store with the latest exposure of each number
+    ///
+    /// Needs IGNORANT_CUSTODY_KEY, the key the stored files were sealed with.
+    Cases {
+        /// Only show this case
+        case_id: Option<String>,
+        /// Only show this number (works on pseudonymized history when the key is set)
+        #[arg(long, num_args = 2, value_names = ["COUNTRY_CODE", "PHONE"])]
+        number: Option<Vec<String>>,
+    },
+    /// Delete stored data past its retention period, or everything about a case or number
+    ///
+    /// Needs IGNORANT_CUSTODY_KEY, the key the stored files were sealed with.
+    Purge {
+        /// Delete everything filed under this case
+        #[arg(long = "case", value_name = "ID", conflicts_with = "number")]
+        case_id: Option<String>,
+        /// Delete everything about this number
+        #[arg(long, num_args = 2, value_names = ["COUNTRY_CODE", "PHONE"])]
+        number: Option<Vec<String>>,
+        /// Report what would be deleted without deleting it
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        dry_run: bool,
+        /// Purge even when some history files cannot be read, leaving them untouched
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        force: bool,
+    },
+    /// Inspect the audit log
+    Audit {
+        #[command(subcommand)]
+        command: AuditCommand,
+    },
+    /// Check that a results file saved with `--output` has not been modified
+    ///
+    /// Needs IGNORANT_CUSTODY_KEY, the key the stored files were sealed with.
+    Verify {
+        /// Results file
+        file: PathBuf,
+    },
+    /// Manage site-definition bundles
+    Sites {
+        #[command(subcommand)]
+        command: SitesCommand,
+    },
+    /// Serve stand-in platforms for demos, training and end-to-end tests
+    MockServer {
+        /// Address to listen on (default: `[mock] listen`, then 127.0.0.1:8443)
+        #[arg(long, value_name = "ADDR")]
+        listen: Option<SocketAddr>,
+        /// Where to write the CA certificate clients must trust (`--ca-cert`)
+        #[arg(long, value_name = "FILE")]
+        ca_out: PathBuf,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum AuditCommand {
+    /// Check that no audit log entry was modified or removed
+    ///
+    /// Needs IGNORANT_CUSTODY_KEY, the key the stored files were sealed with.
+    Verify {
+        /// Audit log (default: `[audit] log` from the configuration)
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SitesCommand {
+    /// Verify a signed site-definition bundle against the trusted keys
+    Verify {
+        /// Bundle directory or .tar.gz archive
+        bundle: PathBuf,
+    },
 }
 
 fn print_colored_text(text: &str, color: &str, no_color: bool) -> String {
@@ -114,6 +516,9 @@ fn print_colored_text(text: &str, color: &str, no_color: bool) -> String {
             "green" => text.green().to_string(),
             "red" => text.red().to_string(),
             "magenta" => text.magenta().to_string(),
+            "yellow" => text.yellow().to_string(),
+            "cyan" => text.cyan().to_string(),
+            "blue" => text.blue().to_string(),
             _ => text.to_owned(),
         }
     }
@@ -122,98 +527,501 @@ fn print_colored_text(text: &str, color: &str, no_color: bool) -> String {
 async fn run_checks(
     phone: String, 
     country_code: String, 
-    client: Client, 
+    modules: &[(Module, HttpClient)],
+    config: &Config,
+    breaker: &Arc<CircuitBreaker>,
+    deadline: Option<tokio::time::Instant>,
     pb: ProgressBar
 ) -> Vec<CheckResult> {
+    let modules: Vec<&(Module, HttpClient)> = modules
+        .iter()
+        .filter(|(module, _)| module.supports(&phone, &country_code))
+        .collect();
+    // Past the deadline nothing is sent, not even a breaker probe
+    if deadline.is_some_and(|deadline| deadline <= tokio::time::Instant::now()) {
+        let now = Utc::now();
+        pb.inc(modules.len() as u64);
+        let mut results: Vec<CheckResult> = modules
+            .into_iter()
+            .map(|(module, _)| module.result().with_timeout("run deadline reached").with_checked_at(now))
+            .collect();
+        results.sort_by(|a, b| a.name.cmp(&b.name));
+        return results;
+    }
     let mut join_set = JoinSet::new();
+    let mut tasks = HashMap::new();
+    let mut results: Vec<Option<CheckResult>> = vec![None; modules.len()];
+    let mut pending = Vec::with_capacity(modules.len());
     
-    // Spawn tasks for each module
-    let phone_clone = phone.clone();
-    let country_code_clone = country_code.clone();
-    let client_clone = client.clone();
-    let pb_clone = pb.clone();
-    join_set.spawn(async move {
-        let result = amazon::check_amazon(&phone_clone, &country_code_clone, &client_clone).await;
-        pb_clone.inc(1);
-        result
-    });
-    
-    let phone_clone = phone.clone();
-    let country_code_clone = country_code.clone();
-    let client_clone = client.clone();
-    let pb_clone = pb.clone();
-    join_set.spawn(async move {
-        let result = instagram::check_instagram(&phone_clone, &country_code_clone, &client_clone).await;
-        pb_clone.inc(1);
-        result
-    });
-    
-    let phone_clone = phone.clone();
-    let country_code_clone = country_code.clone();
-    let client_clone = client.clone();
-    let pb_clone = pb.clone();
-    join_set.spawn(async move {
-        let result = snapchat::check_snapchat(&phone_clone, &country_code_clone, &client_clone).await;
-        pb_clone.inc(1);
-        result
-    });
+    // Spawn a task for each module
+    for (index, (module, client)) in modules.into_iter().cloned().enumerate() {
+        pending.push(module.result());
+        if !breaker.allow(module.name()) {
+            results[index] = Some(module.result().with_skipped().with_checked_at(Utc::now()));
+            pb.inc(1);
+            continue;
+        }
+        let phone = phone.clone();
+        let country_code = country_code.clone();
+        let pb = pb.clone();
+        let policy = RetryPolicy::for_module(&config.retry, module.name());
+        let limit = Timeouts::for_module(&config.timeouts, module.name()).module;
+        let breaker = breaker.clone();
+        let task = join_set.spawn(async move {
+            let client = client.for_check();
+            let check = retry::with_retry(policy, || module.check(&phone, &country_code, &client));
+            let mut result = timeouts::with_timeout(limit, module.result(), check).await.with_checked_at(Utc::now());
+            if module.uses_http() {
+                result.egress = client.egress_used();
+            }
+            breaker.record(module.name(), result.outcome);
+            pb.set_message(breaker_message(&breaker));
+            pb.inc(1);
+            result
+        });
+        tasks.insert(task.id(), index);
+    }
     
-    let mut results = Vec::new();
-    while let Some(result) = join_set.join_next().await {
-        match result {
-            Ok(check_result) => results.push(check_result),
-            Err(e) => eprintln!("Task error: {e}"),
+    loop {
+        let next = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, join_set.join_next_with_id()).await {
+                Ok(next) => next,
+                Err(_) => break,
+            },
+            None => join_set.join_next_with_id().await,
+        };
+        match next {
+            Some(Ok((id, check_result))) => results[tasks[&id]] = Some(check_result),
+            Some(Err(e)) => {
+                eprintln!("Task error: {e}");
+                let index = tasks[&e.id()];
+                results[index] = Some(pending[index].clone().with_error(e.to_string()).with_checked_at(Utc::now()));
+            }
+            None => break,
         }
     }
+    // Whatever is still running when the deadline hits is cancelled
+    join_set.abort_all();
+    let now = Utc::now();
     
+    let mut results: Vec<CheckResult> = results
+        .into_iter()
+        .zip(pending)
+        .map(|(result, pending)| result.unwrap_or_else(|| pending.with_timeout("run deadline reached").with_checked_at(now)))
+        .collect();
     results.sort_by(|a, b| a.name.cmp(&b.name));
     results
 }
 
-fn print_results(
-    results: &[CheckResult],
-    args: &Args,
-    phone: &str,
-    country_code: &str,
-    start_time: Instant,
-    total_modules: usize,
-) {
-    let description = format!(
-        "{}, {}, {}",
-        print_colored_text("[+] Phone number used", "green", args.no_color),
-        print_colored_text("[-] Phone number not used", "magenta", args.no_color),
-        print_colored_text("[x] Rate limit", "red", args.no_color)
+/// Progress bar message listing the modules currently skipped by their breaker
+fn breaker_message(breaker: &CircuitBreaker) -> String {
+    let open = breaker.open_modules();
+    if open.is_empty() {
+        String::new()
+    } else {
+        format!("circuit open: {}", open.join(", "))
+    }
+}
+
+/// Build one HTTP client per distinct set of timeouts and egress, all sharing the rate limiter
+fn build_clients(
+    modules: Vec<Module>,
+    config: &Config,
+    evidence: bool,
+    cassettes: Option<(&Path, cassette::Mode)>,
+    har: Option<&Arc<har::Archive>>,
+) -> Result<Vec<(Module, HttpClient)>> {
+    let limiter = Arc::new(RateLimiter::new(config.rate_limit.clone()));
+    let trust = Trust::load(&config.tls)?;
+    let overrides = resolve::parse(&config.dns.resolve)?;
+    let mut clients: HashMap<(Duration, Duration, Egress, bool), HttpClient> = HashMap::new();
+    let mut scheduled = Vec::with_capacity(modules.len());
+    for module in modules {
+        let timeouts = Timeouts::for_module(&config.timeouts, module.name());
+        let egress = Egress::for_module(&config.proxy, module.name())?;
+        // Plugins follow redirects themselves, within their allowed hosts
+        let sandboxed = matches!(module, Module::Wasm(_));
+        let key = (timeouts.connect, timeouts.read, egress, sandboxed);
+        let client = match clients.get(&key) {
+            Some(client) => client.clone(),
+            None => {
+                let builder = resolve::apply(trust.apply(Client::builder()), &overrides)
+                    .connect_timeout(timeouts.connect)
+                    .timeout(timeouts.read);
+                // Proxies from the environment are already part of the egress
+                let builder = match key.2.proxy() {
+                    Some(proxy) => builder.proxy(proxy),
+                    None => builder.no_proxy(),
+                };
+                let builder = if sandboxed { wasm::sandboxed(builder) } else { builder };
+                let client = HttpClient::new(builder.build()?, limiter.clone())
+                    .with_evidence(evidence)
+                    .with_egress(key.2.clone());
+                clients.insert(key, client.clone());
+                client
+            }
+        };
+        // Each module gets its own cassette on top of the shared client
+        let client = match cassettes {
+            Some((dir, mode)) if module.uses_http() => client.with_cassette(Cassette::open(dir, module.name(), mode)?),
+            _ => client,
+        };
+        let client = match har {
+            Some(archive) if module.uses_http() => client.with_har(archive.capture(module.name())),
+            _ => client,
+        };
+        scheduled.push((module, client));
+    }
+    Ok(scheduled)
+}
+
+/// Load every configured and requested bundle, failing on the first that does not verify
+fn load_site_modules(config: &Config, extra: &[PathBuf]) -> Result<Vec<Module>> {
+    let paths: Vec<&Path> = config.sites.bundles.iter().chain(extra).map(PathBuf::as_path).collect();
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let trusted_keys = bundle::parse_trusted_keys(&config.sites.trusted_keys)?;
+    let mut modules = Vec::new();
+    for path in paths {
+        let bundle = bundle::load(path, &trusted_keys)?;
+        modules.extend(bundle.sites.into_iter().map(|site| Module::Site(Arc::new(site))));
+    }
+    Ok(modules)
+}
+
+/// Load the WebAssembly plugins, skipping (with a warning) any that fail to load
+fn load_wasm_modules(config: &Config, dir: Option<&Path>) -> Result<Vec<Module>> {
+    let Some(dir) = dir.or(config.plugins.wasm.directory.as_deref()) else {
+        return Ok(Vec::new());
+    };
+
+    let engine = wasm::engine();
+    let mut modules = Vec::new();
+    for path in wasm::discover(dir)? {
+        match WasmPlugin::load(&engine, &path, &config.plugins.wasm) {
+            Ok(plugin) => modules.push(Module::Wasm(Arc::new(plugin))),
+            Err(e) => eprintln!("Skipping plugin {}: {e:#}", path.display()),
+        }
+    }
+    Ok(modules)
+}
+
+fn verify_bundle(config: &Config, path: &Path, no_color: bool) -> Result<()> {
+    let trusted_keys = bundle::parse_trusted_keys(&config.sites.trusted_keys)?;
+    let bundle = bundle::load(path, &trusted_keys)?;
+
+    println!(
+        "{}",
+        print_colored_text(
+            &format!("[+] {} {} verified", bundle.manifest.name, bundle.manifest.version),
+            "green",
+            no_color
+        )
     );
-    
-    let full_number = format!("+{} {}", country_code, phone);
-    
-    if !args.no_clear {
-        print!("\x1B[2J\x1B[1;1H"); // Clear screen
+    println!("Signed by : {}", hex::encode(bundle.signer.to_bytes()));
+    println!("Sites     : {}", bundle.sites.len());
+    for site in &bundle.sites {
+        println!("  - {} ({})", site.definition.name, site.definition.domain);
+    }
+    Ok(())
+}
+
+/// Authorization reference of each target, or an error naming those out of scope
+fn authorize_targets(
+    config: &Config,
+    targets: &[batch::Target],
+    pseudonymizer: Option<&Pseudonymizer>,
+) -> Result<BTreeMap<String, String>> {
+    let Some(path) = &config.scope.allowlist else {
+        anyhow::bail!("scope enforcement is on but no [scope] allowlist is configured");
+    };
+    let scope = Scope::load(path)?;
+
+    let mut authorizations = BTreeMap::new();
+    let mut refused = Vec::new();
+    for target in targets {
+        match scope.authorize(target) {
+            Some(authorization) => {
+                authorizations.insert(privacy::stored(pseudonymizer, target), authorization.to_owned());
+            }
+            None => refused.push(target.to_string()),
+        }
+    }
+    if !refused.is_empty() {
+        anyhow::bail!(
+            "refusing to check numbers outside the authorized scope ({}): {}",
+            path.display(),
+            refused.join(", ")
+        );
+    }
+    Ok(authorizations)
+}
+
+/// Number given as `--number <COUNTRY_CODE> <PHONE>`
+fn number_target(number: &[String]) -> batch::Target {
+    batch::Target {
+        country_code: number[0].trim_start_matches('+').to_owned(),
+        phone: number[1].clone(),
+    }
+}
+
+fn purge(
+    config: &Config,
+    case_id: Option<&str>,
+    number: Option<&batch::Target>,
+    dry_run: bool,
+    force: bool,
+    no_color: bool,
+) -> Result<()> {
+    let selector = match (case_id, number) {
+        (Some(case_id), _) => retention::Selector::Case(case_id.to_owned()),
+        (None, Some(number)) => retention::Selector::Number(stored_forms(config, number)?),
+        (None, None) => retention::Selector::Expired(Utc::now()),
+    };
+
+    let mut report = match config.history.directory() {
+        Some(directory) => {
+            let history = History::new(directory, load_chain_key("purge verifies the history against")?).with_vault(load_vault(config)?);
+            retention::purge_history(&history, &config.retention, &selector, dry_run, force)?
+        }
+        None => retention::Report::default(),
+    };
+    if let Some(log) = &config.audit.log {
+        let actor = audit::Actor {
+            host: gethostname::gethostname().to_string_lossy().into_owned(),
+            operator: config.custody.operator.clone(),
+        };
+        report.audit_entries =
+            retention::purge_audit(log, &load_chain_key("purge reseals the audit log with")?, &actor, &config.retention, &selector, dry_run)?;
+    }
+
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    println!("{}", print_colored_text(&format!("[+] {verb} {report}"), "green", no_color));
+    Ok(())
+}
+
+/// Every form a number may have been stored in: in clear, and hashed with each key that is set
+///
+/// Keys the configuration requires must be set; others are used when present,
+/// for data stored before pseudonymization or number hashing was turned on or off.
+fn stored_forms(config: &Config, number: &batch::Target) -> Result<Vec<String>> {
+    let mut forms = vec![number.to_string()];
+    let pseudonymizer = match load_pseudonymizer(config)? {
+        Some(pseudonymizer) => Some(pseudonymizer),
+        None => Pseudonymizer::from_env(privacy::KEY_ENV).ok(),
+    };
+    let audit_hasher = if config.audit.hash_numbers {
+        Some(Pseudonymizer::from_env(audit::KEY_ENV).context("[audit] hash_numbers is on")?)
     } else {
-        println!();
+        Pseudonymizer::from_env(audit::KEY_ENV).ok()
+    };
+    forms.extend(pseudonymizer.iter().chain(&audit_hasher).map(|hasher| hasher.pseudonym(number)));
+    Ok(forms)
+}
+
+fn verify_audit_log(config: &Config, path: Option<&Path>, no_color: bool) -> Result<()> {
+    let Some(path) = path.or(config.audit.log.as_deref()) else {
+        anyhow::bail!("no audit log: pass a file or set [audit] log in the configuration");
+    };
+    let head = audit::verify(path, &load_chain_key("audit verify checks")?)?;
+
+    println!(
+        "{}",
+        print_colored_text(&format!("[+] {} verified ({} entries)", path.display(), head.entries), "green", no_color)
+    );
+    println!("Head     : {}", head.hash);
+    Ok(())
+}
+
+fn verify_results(config: &Config, path: &Path, no_color: bool) -> Result<()> {
+    let vault = load_vault(config)?;
+    let verified = custody::verify(path, &load_chain_key("verify checks")?, vault.as_deref())?;
+    let run = &verified.metadata;
+
+    println!(
+        "{}",
+        print_colored_text(&format!("[+] {} verified", path.display()), "green", no_color)
+    );
+    println!("Run      : {}", run.run_id);
+    println!("Tool     : ignorant {}", run.tool_version);
+    println!("Host     : {}", run.host);
+    if let Some(operator) = &run.operator {
+        println!("Operator : {operator}");
+    }
+    if let Some(case_id) = &run.case_id {
+        println!("Case     : {case_id}");
+    }
+    println!("Started  : {}", run.started_at.to_rfc3339());
+    println!("Finished : {}", run.finished_at.to_rfc3339());
+    println!("Results  : {}", verified.results.len());
+    println!("Head     : {}", verified.head);
+    Ok(())
+}
+
+fn list_cases(config: &Config, only: Option<&str>, number: Option<&batch::Target>, args: &Args) -> Result<()> {
+    let Some(directory) = config.history.directory() else {
+        anyhow::bail!("no history directory: set [history] directory in the configuration");
+    };
+    let runs = History::new(directory, load_chain_key("cases verifies the history against")?).with_vault(load_vault(config)?).runs()?;
+    let mut cases = history::cases(&runs);
+    if let Some(number) = number {
+        let pseudonymizer = load_pseudonymizer(config)?;
+        let stored = privacy::stored(pseudonymizer.as_ref(), number);
+        cases.retain(|_, case| {
+            case.numbers.retain(|key, _| *key == stored);
+            !case.numbers.is_empty()
+        });
+    }
+    let no_color = args.no_color;
+    let cases: Vec<_> = cases.iter().filter(|(case_id, _)| only.is_none_or(|only| only == *case_id)).collect();
+    if cases.is_empty() {
+        println!("No cases found");
+        return Ok(());
     }
+
+    for (case_id, case) in cases {
+        println!(
+            "{}",
+            print_colored_text(
+                &format!("[+] {case_id} ({} run{})", case.runs, if case.runs == 1 { "" } else { "s" }),
+                "green",
+                no_color
+            )
+        );
+        if !case.operators.is_empty() {
+            println!("  Operators : {}", case.operators.join(", "));
+        }
+        for note in &case.notes {
+            println!("  Note      : {note}");
+        }
+        for (number, exposure) in &case.numbers {
+            let number = if masked(config, args) { privacy::mask(number) } else { number.clone() };
+            let found = if exposure.found.is_empty() {
+                "not found".to_owned()
+            } else {
+                format!("found on {}", exposure.found.join(", "))
+            };
+            println!(
+                "  {number}  {}  {found} ({} of {} checks answered)",
+                exposure.checked_at.format("%Y-%m-%d %H:%M UTC"),
+                exposure.answered,
+                exposure.checked
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Marker, color and legend text for each outcome
+fn outcome_style(outcome: Outcome) -> (&'static str, &'static str, &'static str) {
+    match outcome {
+        Outcome::Found => ("[+]", "green", "Phone number used"),
+        Outcome::NotFound => ("[-]", "magenta", "Phone number not used"),
+        Outcome::RateLimited => ("[x]", "red", "Rate limit"),
+        Outcome::Blocked => ("[#]", "red", "Blocked by a challenge"),
+        Outcome::InvalidInput => ("[/]", "yellow", "Invalid number"),
+        Outcome::Error => ("[!]", "yellow", "Error"),
+        Outcome::Unexpected => ("[*]", "yellow", "Unexpected answer"),
+        Outcome::TlsError => ("[%]", "red", "Untrusted certificate"),
+        Outcome::TimedOut => ("[?]", "cyan", "Timed out"),
+        Outcome::Skipped => ("[~]", "blue", "Skipped (circuit open)"),
+    }
+}
+
+/// Pseudonymizer for stored numbers, when `[privacy] pseudonymize` is on
+fn load_pseudonymizer(config: &Config) -> Result<Option<Pseudonymizer>> {
+    if !config.privacy.pseudonymize {
+        return Ok(None);
+    }
+    let pseudonymizer = Pseudonymizer::from_env(privacy::KEY_ENV).context("[privacy] pseudonymize is on")?;
+    Ok(Some(pseudonymizer))
+}
+
+/// Key saved results are chained with, for `reason` (what needs the chain)
+fn load_chain_key(reason: &str) -> Result<custody::ChainKey> {
+    custody::ChainKey::from_env().with_context(|| format!("{reason} a hash chain keyed with {}", custody::KEY_ENV))
+}
+
+/// Storage key, when a passphrase or keyfile is available or `[storage] encrypt` requires one
+fn load_vault(config: &Config) -> Result<Option<Arc<Vault>>> {
+    Ok(Vault::unlock(&config.storage)?.map(Arc::new))
+}
+
+/// Whether printed numbers are masked
+fn masked(config: &Config, args: &Args) -> bool {
+    args.mask || config.privacy.mask_output
+}
+
+fn print_results(results: &[CheckResult], args: &Args, full_number: &str) {
     
     println!("{}", "*".repeat(full_number.len() + 6));
     println!("   {}", full_number);
     println!("{}", "*".repeat(full_number.len() + 6));
     
     for result in results {
-        if result.rate_limit && !args.only_used {
-            println!("{}", print_colored_text(&format!("[x] {}", result.domain), "red", args.no_color));
-        } else if !result.exists && !args.only_used {
-            println!("{}", print_colored_text(&format!("[-] {}", result.domain), "magenta", args.no_color));
-        } else if result.exists {
-            println!("{}", print_colored_text(&format!("[+] {}", result.domain), "green", args.no_color));
+        if args.only_used && result.outcome != Outcome::Found {
+            continue;
+        }
+        let (marker, color, _) = outcome_style(result.outcome);
+        println!("{}", print_colored_text(&format!("{marker} {}", result.domain), color, args.no_color));
+    }
+}
+
+fn print_summary(
+    results: &[CheckResult],
+    args: &Args,
+    breaker: &CircuitBreaker,
+    start_time: Instant,
+    total_numbers: usize,
+) {
+    // The first three are always listed; the others only when they occur
+    let mut legend = vec![Outcome::Found, Outcome::NotFound, Outcome::RateLimited];
+    for outcome in [
+        Outcome::Blocked,
+        Outcome::InvalidInput,
+        Outcome::Error,
+        Outcome::Unexpected,
+        Outcome::TlsError,
+        Outcome::TimedOut,
+        Outcome::Skipped,
+    ] {
+        if results.iter().any(|result| result.outcome == outcome) {
+            legend.push(outcome);
         }
     }
+    let description = legend
+        .into_iter()
+        .map(|outcome| {
+            let (marker, color, text) = outcome_style(outcome);
+            print_colored_text(&format!("{marker} {text}"), color, args.no_color)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
     
     println!();
     println!("{}", description);
-    println!(
-        "{} websites checked in {:.2} seconds",
-        total_modules,
-        start_time.elapsed().as_secs_f64()
-    );
+    for status in breaker.summary() {
+        println!(
+            "Circuit breaker for {}: {} (opened {} times, {} checks skipped)",
+            status.module, status.state, status.trips, status.skipped
+        );
+    }
+    if total_numbers > 1 {
+        println!(
+            "{} checks of {} numbers in {:.2} seconds",
+            results.len(),
+            total_numbers,
+            start_time.elapsed().as_secs_f64()
+        );
+    } else {
+        println!(
+            "{} websites checked in {:.2} seconds",
+            results.len(),
+            start_time.elapsed().as_secs_f64()
+        );
+    }
 }
 
 fn print_credit() {
@@ -225,18 +1033,147 @@ fn print_credit() {
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
+    let mut config = Config::load(args.config.as_deref())?;
+    // Command-line flags take precedence over the configuration file defaults
+    if let Some(timeout) = args.timeout {
+        config.timeouts.read_secs = timeout;
+    }
+    if let Some(timeout) = args.connect_timeout {
+        config.timeouts.connect_secs = timeout;
+    }
+    if let Some(deadline) = args.deadline {
+        config.timeouts.deadline_secs = deadline;
+    }
+    if let Some(proxy) = &args.proxy {
+        config.proxy.url = Some(proxy.clone());
+    }
+    proxy::apply_env(&mut config.proxy);
+    config.tls.ca_certs.extend(args.ca_certs.iter().cloned());
+    config.dns.resolve.extend(args.resolve.iter().cloned());
+    if let Some(roots) = args.tls_roots {
+        config.tls.roots = roots;
+    }
+    if let Some(case_id) = &args.case_id {
+        config.custody.case_id = Some(case_id.clone());
+    }
+    if let Some(operator) = &args.operator {
+        config.custody.operator = Some(operator.clone());
+    }
+
+    match &args.command {
+        Some(Command::Sites { command: SitesCommand::Verify { bundle } }) => {
+            return verify_bundle(&config, bundle, args.no_color);
+        }
+        Some(Command::Verify { file }) => {
+            return verify_results(&config, file, args.no_color);
+        }
+        Some(Command::Audit { command: AuditCommand::Verify { file } }) => {
+            return verify_audit_log(&config, file.as_deref(), args.no_color);
+        }
+        Some(Command::Cases { case_id, number }) => {
+            let number = number.as_deref().map(number_target);
+            return list_cases(&config, case_id.as_deref(), number.as_ref(), &args);
+        }
+        Some(Command::Purge { case_id, number, dry_run, force }) => {
+            let number = number.as_deref().map(number_target);
+            return purge(&config, case_id.as_deref(), number.as_ref(), *dry_run, *force, args.no_color);
+        }
+        Some(Command::MockServer { listen, ca_out }) => {
+            return mock::run(&config.mock, *listen, ca_out).await;
+        }
+        None => {}
+    }
+
+    let targets = match (&args.batch, &args.country_code, &args.phone) {
+        (Some(path), _, _) => batch::load(path)?,
+        (None, Some(country_code), Some(phone)) => vec![batch::Target {
+            country_code: country_code.clone(),
+            phone: phone.clone(),
+        }],
+        _ => unreachable!("clap requires both positional arguments without a subcommand or batch"),
+    };
+
+    // Nothing is sent until every number is known to be in scope
+    let pseudonymizer = load_pseudonymizer(&config)?;
+    let authorizations = if args.enforce_scope || config.scope.enforce {
+        authorize_targets(&config, &targets, pseudonymizer.as_ref())?
+    } else {
+        BTreeMap::new()
+    };
+
+    let audit = AuditLog::open(&config.audit, pseudonymizer.as_ref())?;
+    // The audit log is never encrypted, so it may only hold hashed numbers
+    if config.storage.encrypt && audit.as_ref().is_some_and(|audit| audit.pseudonymizer().is_none()) {
+        anyhow::bail!(
+            "[storage] encrypt is on but the audit log would store numbers in clear: \
+             set [audit] hash_numbers or [privacy] pseudonymize"
+        );
+    }
+    // Cassettes hold the numbers and every response in clear
+    if config.storage.encrypt && args.record.is_some() {
+        anyhow::bail!("[storage] encrypt is on but --record would store traffic in clear");
+    }
+    if config.privacy.pseudonymize && args.record.is_some() {
+        anyhow::bail!("[privacy] pseudonymize is on but --record would store numbers in clear");
+    }
+    if config.storage.encrypt && args.har.is_some() {
+        anyhow::bail!("[storage] encrypt is on but --har would store traffic in clear");
+    }
+    // A run is only kept when asked for: with --output, or in the history under a case
+    let keeps_results = args.output.is_some() || config.custody.case_id.is_some();
+    let vault = if keeps_results { load_vault(&config)? } else { None };
+    let chain_key = if keeps_results { Some(load_chain_key("--output and --case save results sealed with")?) } else { None };
+    let run_id = uuid::Uuid::new_v4().to_string();
+    let host = gethostname::gethostname().to_string_lossy().into_owned();
+
+    let mut modules = modules::builtin();
+    modules.extend(load_site_modules(&config, &args.sites)?);
+    modules.extend(load_wasm_modules(&config, args.plugin_dir.as_deref())?);
+    modules.extend(config.plugins.process.iter().cloned().map(|plugin| Module::Process(Arc::new(plugin))));
     
-    print_credit();
+    if !args.json {
+        print_credit();
+    }
     
-    let client = Client::builder()
-        .timeout(std::time::Duration::from_secs(args.timeout))
-        .build()?;
+    let cassettes = match (&args.record, &args.replay) {
+        (Some(dir), _) => Some((dir.as_path(), cassette::Mode::Record)),
+        (None, Some(dir)) => Some((dir.as_path(), cassette::Mode::Replay)),
+        (None, None) => None,
+    };
+    let har = args.har.as_ref().map(|_| {
+        let mut redactions = config.har.redact.clone();
+        redactions.extend(&args.har_redact);
+        // Pseudonymized runs never write numbers to disk
+        if config.privacy.pseudonymize {
+            redactions.push(config::HarRedaction::Number);
+        }
+        Arc::new(har::Archive::new(&redactions, targets.iter().flat_map(privacy::needles)))
+    });
+    let modules = build_clients(modules, &config, args.evidence, cassettes, har.as_ref())?;
+    let deadline = (config.timeouts.deadline_secs > 0)
+        .then(|| tokio::time::Instant::now() + Duration::from_secs(config.timeouts.deadline_secs));
+    let breaker = Arc::new(CircuitBreaker::new(config.circuit_breaker.clone()));
     
-    let total_modules = 3;
+    let total_checks: usize = targets
+        .iter()
+        .map(|target| modules.iter().filter(|(module, _)| module.supports(&target.phone, &target.country_code)).count())
+        .sum();
     let start_time = Instant::now();
+    let started_at = Utc::now();
+    let audit_template = audit::Entry {
+        timestamp: started_at,
+        run_id: run_id.clone(),
+        host: host.clone(),
+        operator: config.custody.operator.clone(),
+        case_id: config.custody.case_id.clone(),
+        number: String::new(),
+        stage: audit::Stage::Finished,
+        modules: Vec::new(),
+        results: BTreeMap::new(),
+    };
     
     // Create progress bar
-    let pb = ProgressBar::new(total_modules);
+    let pb = ProgressBar::new(total_checks as u64);
     pb.set_style(
         ProgressStyle::default_bar()
             .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} {msg}")
@@ -244,17 +1181,153 @@ async fn main() -> Result<()> {
             .progress_chars("█░"),
     );
     
-    let results = run_checks(
-        args.phone.clone(),
-        args.country_code.clone(),
-        client,
-        pb.clone(),
-    ).await;
+    let mut runs = Vec::with_capacity(targets.len());
+    for target in &targets {
+        // Logged before anything is sent, so a run killed halfway still shows what it checked
+        if let Some(audit) = &audit {
+            let names: Vec<&str> = modules
+                .iter()
+                .filter(|(module, _)| module.supports(&target.phone, &target.country_code))
+                .map(|(module, _)| module.name())
+                .collect();
+            audit.append(&audit.started(&audit_template, target, &names))?;
+        }
+        let results = run_checks(
+            target.phone.clone(),
+            target.country_code.clone(),
+            &modules,
+            &config,
+            &breaker,
+            deadline,
+            pb.clone(),
+        ).await;
+        if let Some(audit) = &audit {
+            audit.append(&audit.entry(&audit_template, target, &results))?;
+        }
+        runs.push(results);
+    }
     
     pb.finish_and_clear();
+    if let (Some(archive), Some(path)) = (&har, &args.har) {
+        archive.write(path)?;
+    }
+    
+    let history = config
+        .custody
+        .case_id
+        .as_ref()
+        .and(config.history.directory())
+        .zip(chain_key.clone())
+        .map(|(directory, key)| History::new(directory, key).with_vault(vault.clone()));
+    if args.output.is_some() || history.is_some() {
+        let metadata = custody::RunMetadata {
+            run_id: run_id.clone(),
+            tool_version: VERSION.to_owned(),
+            modules: modules
+                .iter()
+                .map(|(module, _)| (module.name().to_owned(), module.version().unwrap_or("unversioned").to_owned()))
+                .collect(),
+            host: host.clone(),
+            operator: config.custody.operator.clone(),
+            case_id: config.custody.case_id.clone(),
+            authorizations: authorizations.clone(),
+            note: args.note.clone(),
+            started_at,
+            finished_at: Utc::now(),
+        };
+        let records: Vec<(String, CheckResult)> = targets
+            .iter()
+            .zip(&runs)
+            .flat_map(|(target, results)| {
+                let number = privacy::stored(pseudonymizer.as_ref(), target);
+                let redact = pseudonymizer.is_some();
+                results.iter().map(move |result| {
+                    let result = if redact { privacy::redact(result, target) } else { result.clone() };
+                    (number.clone(), result)
+                })
+            })
+            .collect();
+        let key = chain_key.as_ref().expect("the chain key is loaded when results are kept");
+        if let Some(path) = &args.output {
+            let head = custody::write(path, &metadata, &records, key, vault.as_deref())?;
+            eprintln!("Results written to {} (chain head {head})", path.display());
+        }
+        if let Some(history) = &history {
+            history.save(&metadata, &records)?;
+        }
+    }
     
-    print_results(&results, &args, &args.phone, &args.country_code, start_time, total_modules as usize);
+    if args.json {
+        let report: Vec<NumberResults> = targets
+            .iter()
+            .zip(&runs)
+            .map(|(target, results)| NumberResults {
+                country_code: &target.country_code,
+                phone: if masked(&config, &args) { privacy::mask_phone(&target.phone) } else { target.phone.clone() },
+                results,
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    
+    if !args.no_clear {
+        print!("\x1B[2J\x1B[1;1H"); // Clear screen
+    } else {
+        println!();
+    }
+    for (target, results) in targets.iter().zip(&runs) {
+        let number = if masked(&config, &args) { privacy::mask(&target.to_string()) } else { target.to_string() };
+        print_results(results, &args, &number);
+    }
+    let all_results: Vec<CheckResult> = runs.into_iter().flatten().collect();
+    print_summary(&all_results, &args, &breaker, start_time, targets.len());
     print_credit();
     
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CircuitBreakerConfig;
+    use crate::sites::{Site, SiteDefinition};
+
+    #[tokio::test]
+    async fn test_no_request_after_deadline() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/check")
+            .with_body("taken")
+            .expect(1)
+            .create_async()
+            .await;
+        let definition: SiteDefinition = serde_json::from_value(serde_json::json!({
+            "name": "example",
+            "domain": "example.com",
+            "method": "register",
+            "request": {"url": format!("{}/check", server.url())},
+            "found": {"body_contains": "taken"},
+            "not_found": {"body_contains": "free"}
+        }))
+        .unwrap();
+        let site = Site { definition, bundle_version: "1.0.0".to_owned() };
+        let modules = [(Module::Site(Arc::new(site)), HttpClient::from(Client::new()))];
+        let config = Config::default();
+        let breaker = Arc::new(CircuitBreaker::new(CircuitBreakerConfig { failure_threshold: 1, cooldown_secs: 0 }));
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(10);
+
+        let first = run_checks("644637111".to_owned(), "33".to_owned(), &modules, &config, &breaker, Some(deadline), ProgressBar::hidden()).await;
+        // An open breaker past its cool-down would let a probe through
+        breaker.record("example", Outcome::Error);
+        tokio::time::pause();
+        tokio::time::advance(Duration::from_secs(10)).await;
+        let second = run_checks("644637111".to_owned(), "33".to_owned(), &modules, &config, &breaker, Some(deadline), ProgressBar::hidden()).await;
+
+        mock.assert_async().await;
+        assert_eq!(first[0].outcome, Outcome::Found);
+        assert_eq!(second[0].outcome, Outcome::TimedOut);
+        assert_eq!(second[0].error.as_deref(), Some("run deadline reached"));
+        assert_eq!(breaker.summary()[0].state, breaker::State::Open);
+    }
+}
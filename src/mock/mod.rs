@@ -0,0 +1,335 @@
+//! Stand-in platforms
+//!
+//! `ignorant mock-server` answers the requests of the built-in modules the
+//! way Amazon, Instagram and Snapchat do, for the numbers registered in
+//! `[mock.numbers]`. It serves HTTPS with a certificate generated at start-up
+//! and written to `--ca-out`, which clients trust with `--ca-cert`.
+//!
+//! Clients reach it in either of two ways:
+//!
+//! - as their proxy (`--proxy http://ADDR`): the server accepts `CONNECT` to
+//!   the emulated hosts and terminates TLS itself, on any port;
+//! - directly, with `--resolve HOST:443:ADDR` for each host, which needs the
+//!   server to listen on port 443.
+
+mod platforms;
+
+use std::collections::HashMap;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{Context, Result, bail};
+use openssl::asn1::Asn1Time;
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
+use openssl::x509::extension::{BasicConstraints, SubjectAlternativeName, SubjectKeyIdentifier};
+use openssl::x509::{X509Builder, X509NameBuilder};
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_native_tls::TlsAcceptor;
+
+use crate::config::MockConfig;
+use platforms::Platforms;
+
+/// Address listened on when neither `--listen` nor `[mock] listen` is set
+const DEFAULT_LISTEN: SocketAddr = SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 8443);
+
+/// Largest request head or body accepted
+const MAX_REQUEST_BYTES: usize = 1024 * 1024;
+
+/// First byte of a TLS handshake record
+const TLS_HANDSHAKE: u8 = 0x16;
+
+/// HTTP/1.1 request, with header names in lowercase
+struct Request {
+    method: String,
+    target: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header == name).map(|(_, value)| value.as_str())
+    }
+
+    /// Host the request is for, without its port
+    fn host(&self) -> &str {
+        let host = self.header("host").unwrap_or_default();
+        host.rsplit_once(':').map_or(host, |(host, _)| host)
+    }
+
+    /// Request target without its query
+    fn path(&self) -> &str {
+        self.target.split_once('?').map_or(self.target.as_str(), |(path, _)| path)
+    }
+
+    /// Fields of a URL-encoded form body
+    fn form(&self) -> HashMap<String, String> {
+        url::form_urlencoded::parse(&self.body).into_owned().collect()
+    }
+}
+
+/// HTTP/1.1 response; the connection is closed once it is sent
+struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: String,
+}
+
+impl Response {
+    fn new(status: u16, content_type: &str, body: impl Into<String>) -> Self {
+        Self { status, headers: vec![("Content-Type", content_type.to_owned())], body: body.into() }
+    }
+
+    fn text(status: u16, body: &str) -> Self {
+        Self::new(status, "text/plain; charset=utf-8", format!("{body}\n"))
+    }
+
+    fn html(body: impl Into<String>) -> Self {
+        Self::new(200, "text/html; charset=utf-8", body)
+    }
+
+    fn json(status: u16, body: Value) -> Self {
+        Self::new(status, "application/json", body.to_string())
+    }
+
+    fn with_header(mut self, name: &'static str, value: String) -> Self {
+        self.headers.push((name, value));
+        self
+    }
+}
+
+/// Read one request, or `None` when the peer closes the connection first
+async fn read_request<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Option<Request>> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0; 4096];
+    let head_end = loop {
+        if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end;
+        }
+        if buffer.len() > MAX_REQUEST_BYTES {
+            bail!("request head too large");
+        }
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            if buffer.is_empty() {
+                return Ok(None);
+            }
+            bail!("connection closed in the middle of a request");
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    };
+
+    let head = std::str::from_utf8(&buffer[..head_end]).context("request head is not UTF-8")?;
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        bail!("malformed request line");
+    };
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_owned()))
+        .collect();
+    let mut request = Request { method: method.to_owned(), target: target.to_owned(), headers, body: Vec::new() };
+
+    let length: usize = match request.header("content-length") {
+        Some(length) => length.parse().context("invalid Content-Length")?,
+        None => 0,
+    };
+    if length > MAX_REQUEST_BYTES {
+        bail!("request body too large");
+    }
+    let mut body = buffer.split_off(head_end + 4);
+    if body.len() < length {
+        let start = body.len();
+        body.resize(length, 0);
+        stream.read_exact(&mut body[start..]).await?;
+    }
+    body.truncate(length);
+    request.body = body;
+    Ok(Some(request))
+}
+
+async fn write_response<S: AsyncWrite + Unpin>(stream: &mut S, response: &Response) -> Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        _ => "",
+    };
+    let mut head = format!("HTTP/1.1 {} {reason}\r\n", response.status);
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str(&format!("Content-Length: {}\r\nConnection: close\r\n\r\n", response.body.len()));
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Self-signed certificate for the emulated hosts, and its key, both in PEM
+fn certificate(hosts: &[String]) -> Result<(String, String)> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, "ignorant mock platforms")?;
+    let name = name.build();
+    let mut serial = BigNum::new()?;
+    serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+    let serial = serial.to_asn1_integer()?;
+    let not_before = Asn1Time::days_from_now(0)?;
+    let not_after = Asn1Time::days_from_now(30)?;
+
+    let mut builder = X509Builder::new()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&key)?;
+    builder.set_not_before(&not_before)?;
+    builder.set_not_after(&not_after)?;
+    builder.append_extension(BasicConstraints::new().critical().ca().build()?)?;
+    let key_id = SubjectKeyIdentifier::new().build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(key_id)?;
+    let mut names = SubjectAlternativeName::new();
+    for host in hosts {
+        names.dns(host);
+    }
+    names.dns("localhost").ip("127.0.0.1");
+    let names = names.build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(names)?;
+    builder.sign(&key, MessageDigest::sha256())?;
+
+    let cert = String::from_utf8(builder.build().to_pem()?)?;
+    let key = String::from_utf8(key.private_key_to_pem_pkcs8()?)?;
+    Ok((cert, key))
+}
+
+/// Serve one connection: a `CONNECT` tunnel or direct TLS, then a single request
+async fn connection(mut socket: TcpStream, acceptor: TlsAcceptor, platforms: Arc<Platforms>) -> Result<()> {
+    let mut first = [0; 1];
+    if socket.peek(&mut first).await? == 0 {
+        return Ok(());
+    }
+    if first[0] != TLS_HANDSHAKE {
+        let Some(request) = read_request(&mut socket).await? else {
+            return Ok(());
+        };
+        let host = request.target.rsplit_once(':').map_or(request.target.as_str(), |(host, _)| host);
+        if request.method != "CONNECT" || !platforms::hosts().iter().any(|known| known == host) {
+            println!("{} {} -> 403", request.method, request.target);
+            let response = Response::text(403, "only CONNECT to an emulated platform is served");
+            return write_response(&mut socket, &response).await;
+        }
+        socket.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n").await?;
+    }
+
+    let mut stream = acceptor.accept(socket).await.context("TLS handshake failed")?;
+    if let Some(request) = read_request(&mut stream).await? {
+        let response = platforms.handle(&request);
+        println!("{} https://{}{} -> {}", request.method, request.host(), request.target, response.status);
+        write_response(&mut stream, &response).await?;
+    }
+    Ok(())
+}
+
+/// Accept connections on `listener` until the process stops
+async fn serve(listener: TcpListener, acceptor: TlsAcceptor, platforms: Arc<Platforms>) -> Result<()> {
+    loop {
+        let (socket, peer) = listener.accept().await?;
+        let acceptor = acceptor.clone();
+        let platforms = platforms.clone();
+        tokio::spawn(async move {
+            if let Err(e) = connection(socket, acceptor, platforms).await {
+                eprintln!("{peer}: {e:#}");
+            }
+        });
+    }
+}
+
+/// Serve the stand-in platforms, writing their CA certificate to `ca_out`
+pub async fn run(config: &MockConfig, listen: Option<SocketAddr>, ca_out: &Path) -> Result<()> {
+    let platforms = Arc::new(Platforms::new(config)?);
+    let (cert, key) = certificate(&platforms::hosts()).context("failed to generate the mock certificate")?;
+    fs::write(ca_out, &cert).with_context(|| format!("failed to write {}", ca_out.display()))?;
+    let identity = native_tls::Identity::from_pkcs8(cert.as_bytes(), key.as_bytes())?;
+    let acceptor = TlsAcceptor::from(native_tls::TlsAcceptor::new(identity)?);
+
+    let addr = listen.or(config.listen).unwrap_or(DEFAULT_LISTEN);
+    let listener = TcpListener::bind(addr).await.with_context(|| format!("failed to listen on {addr}"))?;
+    let addr = listener.local_addr()?;
+    println!("Mock platforms listening on {addr} ({} registered numbers)", platforms.registered());
+    println!("CA certificate written to {}", ca_out.display());
+    println!("Check numbers with: ignorant COUNTRY_CODE PHONE --proxy http://{addr} --ca-cert {}", ca_out.display());
+    serve(listener, acceptor, platforms).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Outcome;
+    use crate::http::HttpClient;
+    use crate::modules::{amazon, instagram, snapchat};
+
+    /// Start the server and return a client going through it
+    async fn client() -> HttpClient {
+        let mut config = MockConfig::default();
+        config.numbers.insert("+33644637111".to_owned(), vec!["amazon_fr".to_owned(), "instagram".to_owned(), "snapchat".to_owned()]);
+        let platforms = Arc::new(Platforms::new(&config).unwrap());
+        let (cert, key) = certificate(&platforms::hosts()).unwrap();
+        let identity = native_tls::Identity::from_pkcs8(cert.as_bytes(), key.as_bytes()).unwrap();
+        let acceptor = TlsAcceptor::from(native_tls::TlsAcceptor::new(identity).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, acceptor, platforms));
+
+        let client = reqwest::Client::builder()
+            .proxy(reqwest::Proxy::all(format!("http://{addr}")).unwrap())
+            .add_root_certificate(reqwest::Certificate::from_pem(cert.as_bytes()).unwrap())
+            .build()
+            .unwrap();
+        HttpClient::from(client)
+    }
+
+    #[tokio::test]
+    async fn test_modules_against_mock() {
+        let client = client().await;
+        let marketplace = amazon::marketplace_for("644637111", "33");
+
+        for (phone, outcome) in [("644637111", Outcome::Found), ("644637112", Outcome::NotFound)] {
+            assert_eq!(amazon::check_amazon(marketplace, phone, "33", &client).await.outcome, outcome);
+            assert_eq!(instagram::check_instagram(phone, "33", &client).await.outcome, outcome);
+            assert_eq!(snapchat::check_snapchat(phone, "33", &client).await.outcome, outcome);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unknown_hosts_are_refused() {
+        let client = client().await;
+
+        let error = client.get("https://example.com/").send().await.unwrap_err();
+
+        assert!(error.is_connect(), "{error:?}");
+    }
+
+    #[tokio::test]
+    async fn test_read_request_body() {
+        let raw = b"POST /ap/signin/ HTTP/1.1\r\nHost: www.amazon.fr:443\r\nContent-Length: 11\r\n\r\nemail=33644";
+        let request = read_request(&mut &raw[..]).await.unwrap().unwrap();
+
+        assert_eq!(request.host(), "www.amazon.fr");
+        assert_eq!(request.path(), "/ap/signin/");
+        assert_eq!(request.form()["email"], "33644");
+        assert!(read_request(&mut &b""[..]).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,270 @@
+//! Emulated platform endpoints
+//!
+//! Each handler answers the way the real platform does as far as the
+//! built-in modules can tell, for registered numbers and for the rest.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::sync::Mutex;
+
+use anyhow::{Result, bail};
+use rand::Rng;
+use serde_json::{Value, json};
+
+use super::{Request, Response};
+use crate::config::MockConfig;
+use crate::modules::amazon::MARKETPLACES;
+use crate::modules::snapchat;
+
+const INSTAGRAM_HOST: &str = "i.instagram.com";
+const SNAPCHAT_HOST: &str = "accounts.snapchat.com";
+
+/// Hosts the mock server answers for
+pub fn hosts() -> Vec<String> {
+    MARKETPLACES
+        .iter()
+        .map(|marketplace| format!("www.{}", marketplace.domain))
+        .chain([INSTAGRAM_HOST.to_owned(), SNAPCHAT_HOST.to_owned()])
+        .collect()
+}
+
+/// Whether `platform` names something numbers can be registered on
+fn is_platform(platform: &str) -> bool {
+    matches!(platform, "amazon" | "instagram" | "snapchat") || MARKETPLACES.iter().any(|marketplace| marketplace.name == platform)
+}
+
+/// Registered numbers and the session state of the emulated platforms
+#[derive(Debug)]
+pub struct Platforms {
+    /// Platforms each number is registered on, keyed by country code and phone digits
+    numbers: BTreeMap<String, BTreeSet<String>>,
+    /// xsrf tokens handed out by the Snapchat home page
+    xsrf_tokens: Mutex<HashSet<String>>,
+}
+
+impl Platforms {
+    pub fn new(config: &MockConfig) -> Result<Self> {
+        let mut numbers = BTreeMap::new();
+        for (number, platforms) in &config.numbers {
+            let digits: String = number.chars().filter(|c| !matches!(c, '+' | ' ' | '-')).collect();
+            if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+                bail!("[mock.numbers]: `{number}` is not a phone number");
+            }
+            if let Some(platform) = platforms.iter().find(|platform| !is_platform(platform)) {
+                bail!("[mock.numbers]: unknown platform `{platform}` for {number}");
+            }
+            numbers.insert(digits, platforms.iter().cloned().collect());
+        }
+        Ok(Self { numbers, xsrf_tokens: Mutex::default() })
+    }
+
+    /// Number of registered numbers
+    pub fn registered(&self) -> usize {
+        self.numbers.len()
+    }
+
+    /// Whether a number, as country code and phone digits, is registered on a platform
+    ///
+    /// Amazon platforms are module names, so `amazon` is amazon.com only.
+    fn is_registered(&self, number: &str, platform: &str) -> bool {
+        self.numbers.get(number).is_some_and(|platforms| platforms.contains(platform))
+    }
+
+    /// Answer a request as the platform behind its host would
+    pub fn handle(&self, request: &Request) -> Response {
+        match (request.host(), request.method.as_str(), request.path()) {
+            (INSTAGRAM_HOST, "POST", "/api/v1/users/lookup/") => self.instagram_lookup(request),
+            (SNAPCHAT_HOST, "GET", "/") => self.snapchat_home(),
+            (SNAPCHAT_HOST, "POST", "/accounts/validate_phone_number") => self.snapchat_validate(request),
+            (host, method, path) => match amazon_marketplace(host) {
+                Some(_) if method == "GET" && path == "/ap/signin" => amazon_signin(),
+                Some(marketplace) if method == "POST" && path == "/ap/signin/" => self.amazon_submit(marketplace, request),
+                _ => Response::text(404, "not found"),
+            },
+        }
+    }
+
+    fn amazon_submit(&self, marketplace: &str, request: &Request) -> Response {
+        let form = request.form();
+        if !form.contains_key("appActionToken") {
+            return Response::text(400, "sign-in form submitted without its hidden fields");
+        }
+        let email = form.get("email").map(String::as_str).unwrap_or_default();
+        if self.is_registered(email, marketplace) {
+            Response::html(
+                "<form name=\"signIn\" method=\"post\">\n\
+                 <div id=\"auth-password-missing-alert\" class=\"a-alert-inline\">Enter your password</div>\n\
+                 <input type=\"password\" name=\"password\" id=\"ap_password\">\n\
+                 </form>\n",
+            )
+        } else {
+            Response::html(
+                "<div id=\"auth-error-message-box\">\n\
+                 <h4>There was a problem</h4>\n\
+                 <span>We cannot find an account with that email address</span>\n\
+                 </div>\n",
+            )
+        }
+    }
+
+    fn instagram_lookup(&self, request: &Request) -> Response {
+        // `signed_body` is `<signature>.<JSON data>`; the signature is not checked
+        let query = request
+            .form()
+            .get("signed_body")
+            .and_then(|body| body.split_once('.'))
+            .and_then(|(_, data)| serde_json::from_str::<Value>(data).ok())
+            .and_then(|data| data.get("q").and_then(Value::as_str).map(str::to_owned));
+        let Some(number) = query else {
+            return Response::json(400, json!({ "status": "fail", "message": "Invalid parameters" }));
+        };
+        if self.is_registered(&number, "instagram") {
+            let user = json!({ "pk": 1, "username": format!("mock_{number}"), "full_name": "Mock User" });
+            Response::json(200, json!({ "status": "ok", "user": user }))
+        } else {
+            Response::json(404, json!({ "status": "fail", "message": "No users found", "error_type": "no_users_found" }))
+        }
+    }
+
+    fn snapchat_home(&self) -> Response {
+        let token = hex::encode(rand::thread_rng().r#gen::<[u8; 16]>());
+        self.xsrf_tokens.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).insert(token.clone());
+        Response::html("<html><head><title>Snapchat</title></head><body></body></html>\n")
+            .with_header("Set-Cookie", format!("xsrf_token={token}; Path=/; Secure"))
+    }
+
+    fn snapchat_validate(&self, request: &Request) -> Response {
+        let form = request.form();
+        let field = |name: &str| form.get(name).map(String::as_str).unwrap_or_default();
+        let known_token = self
+            .xsrf_tokens
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .contains(field("xsrf_token"));
+        if !known_token {
+            return Response::json(200, json!({ "status_code": "INVALID_XSRF_TOKEN" }));
+        }
+        let country = field("phone_country_code");
+        let calling_codes: Vec<&str> = snapchat::get_country_code_map()
+            .into_iter()
+            .filter(|(_, iso)| *iso == country)
+            .map(|(code, _)| code)
+            .collect();
+        if calling_codes.is_empty() {
+            return Response::json(200, json!({ "status_code": "UNSUPPORTED_COUNTRY" }));
+        }
+        let phone = field("phone_number");
+        let taken = calling_codes
+            .iter()
+            .any(|code| self.is_registered(&format!("{code}{phone}"), "snapchat"));
+        Response::json(200, json!({ "status_code": if taken { "TAKEN_NUMBER" } else { "OK" } }))
+    }
+}
+
+/// Module name of the marketplace served at `host`
+fn amazon_marketplace(host: &str) -> Option<&'static str> {
+    let domain = host.strip_prefix("www.")?;
+    MARKETPLACES
+        .iter()
+        .find(|marketplace| marketplace.domain == domain)
+        .map(|marketplace| marketplace.name)
+}
+
+/// Sign-in form, with the hidden fields the module sends back
+fn amazon_signin() -> Response {
+    let token = hex::encode(rand::thread_rng().r#gen::<[u8; 16]>());
+    Response::html(format!(
+        "<html>\n<head><title>Amazon Sign-In</title></head>\n<body>\n\
+         <form name=\"signIn\" method=\"post\" action=\"/ap/signin/\">\n\
+         <input type=\"hidden\" name=\"appActionToken\" value=\"{token}\">\n\
+         <input type=\"hidden\" name=\"appAction\" value=\"SIGNIN_PWD_COLLECT\">\n\
+         <input type=\"email\" name=\"email\" id=\"ap_email\">\n\
+         </form>\n</body>\n</html>\n"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn platforms() -> Platforms {
+        let config: crate::config::Config = toml::from_str(
+            r#"
+            [mock.numbers]
+            "+33 644637111" = ["amazon_fr", "instagram", "snapchat"]
+            "+44 7700900123" = ["amazon", "amazon_fr"]
+            "#,
+        )
+        .unwrap();
+        Platforms::new(&config.mock).unwrap()
+    }
+
+    fn request(method: &str, host: &str, target: &str, body: &str) -> Request {
+        Request {
+            method: method.to_owned(),
+            target: target.to_owned(),
+            headers: vec![("host".to_owned(), host.to_owned())],
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_invalid_numbers_table() {
+        let mut config = MockConfig::default();
+        config.numbers.insert("+33 644637111".to_owned(), vec!["myspace".to_owned()]);
+        assert!(Platforms::new(&config).unwrap_err().to_string().contains("unknown platform `myspace`"));
+
+        let mut config = MockConfig::default();
+        config.numbers.insert("call me".to_owned(), vec!["amazon".to_owned()]);
+        assert!(Platforms::new(&config).is_err());
+    }
+
+    #[test]
+    fn test_amazon_marketplaces() {
+        let platforms = platforms();
+        let form = platforms.handle(&request("GET", "www.amazon.fr", "/ap/signin?openid.mode=checkid_setup", ""));
+        assert!(form.body.contains("name=\"appActionToken\""));
+
+        let submit = |host: &str, email: &str| {
+            let body = format!("appActionToken=abc&email={email}");
+            platforms.handle(&request("POST", host, "/ap/signin/", &body)).body
+        };
+        assert!(submit("www.amazon.fr", "33644637111").contains("auth-password-missing-alert"));
+        assert!(!submit("www.amazon.com", "33644637111").contains("auth-password-missing-alert"));
+        assert!(submit("www.amazon.com", "447700900123").contains("auth-password-missing-alert"));
+        assert!(submit("www.amazon.fr", "447700900123").contains("auth-password-missing-alert"));
+        assert!(!submit("www.amazon.co.uk", "447700900123").contains("auth-password-missing-alert"));
+        assert_eq!(platforms.handle(&request("POST", "www.amazon.fr", "/ap/signin/", "email=33644637111")).status, 400);
+    }
+
+    #[test]
+    fn test_instagram_lookup() {
+        let platforms = platforms();
+        let lookup = |number: &str| {
+            let data = format!("{{\"q\":\"{number}\"}}");
+            let body: String = url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("ig_sig_key_version", "4")
+                .append_pair("signed_body", &format!("0123abcd.{data}"))
+                .finish();
+            platforms.handle(&request("POST", INSTAGRAM_HOST, "/api/v1/users/lookup/", &body))
+        };
+
+        assert!(lookup("33644637111").body.contains("\"user\""));
+        assert!(lookup("447700900123").body.contains("No users found"));
+    }
+
+    #[test]
+    fn test_snapchat_requires_xsrf_token() {
+        let platforms = platforms();
+        let home = platforms.handle(&request("GET", SNAPCHAT_HOST, "/", ""));
+        let cookie = &home.headers.iter().find(|(name, _)| *name == "Set-Cookie").unwrap().1;
+        let token = cookie.trim_start_matches("xsrf_token=").split(';').next().unwrap();
+        let validate = |phone: &str, token: &str| {
+            let body = format!("phone_country_code=FR&phone_number={phone}&xsrf_token={token}");
+            platforms.handle(&request("POST", SNAPCHAT_HOST, "/accounts/validate_phone_number", &body)).body
+        };
+
+        assert!(validate("644637111", token).contains("TAKEN_NUMBER"));
+        assert!(validate("644637112", token).contains("\"OK\""));
+        assert!(validate("644637111", "forged").contains("INVALID_XSRF_TOKEN"));
+    }
+}
@@ -2,9 +2,12 @@
 //! 
 //! This module checks if a phone number is associated with an Amazon account
 //! by attempting to sign in and analyzing the response for password prompts.
+//!
+//! Amazon marketplaces keep separate accounts, so each one is checked on its
+//! own sign-in flow. The marketplace is chosen from the number's country;
+//! countries without a marketplace of their own use amazon.com.
 
-use crate::{CheckResult, user_agents::USER_AGENTS};
-use reqwest::Client;
+use crate::{CheckResult, challenge, evidence, http::HttpClient, user_agents::USER_AGENTS};
 use std::collections::HashMap;
 use rand::Rng;
 
@@ -15,82 +18,171 @@ fn extract_form_data(html_content: &str) -> HashMap<String, String> {
     // Extract CSRF tokens and other hidden inputs using simple string parsing
     let lines: Vec<&str> = html_content.lines().collect();
     for line in lines {
-        if line.contains("<input") && line.contains("type=\"hidden\"") {
-            if let (Some(name_start), Some(value_start)) = (
+        if line.contains("<input") && line.contains("type=\"hidden\"")
+            && let (Some(name_start), Some(value_start)) = (
                 line.find("name=\"").map(|i| i + 6),
                 line.find("value=\"").map(|i| i + 7)
-            ) {
-                if let (Some(name_end), Some(value_end)) = (
-                    line[name_start..].find('\"').map(|i| i + name_start),
-                    line[value_start..].find('\"').map(|i| i + value_start)
-                ) {
-                    let name = &line[name_start..name_end];
-                    let value = &line[value_start..value_end];
-                    form_data.insert(name.to_string(), value.to_string());
-                }
-            }
+            )
+            && let (Some(name_end), Some(value_end)) = (
+                line[name_start..].find('\"').map(|i| i + name_start),
+                line[value_start..].find('\"').map(|i| i + value_start)
+            )
+        {
+            let name = &line[name_start..name_end];
+            let value = &line[value_start..value_end];
+            form_data.insert(name.to_string(), value.to_string());
         }
     }
     form_data
 }
 
-const AMAZON_SIGNIN_URL: &str = "https://www.amazon.com/ap/signin?openid.pape.max_auth_age=0&openid.return_to=https%3A%2F%2Fwww.amazon.com%2F%3F_encoding%3DUTF8%26ref_%3Dnav_ya_signin&openid.identity=http%3A%2F%2Fspecs.openid.net%2Fauth%2F2.0%2Fidentifier_select&openid.assoc_handle=usflex&openid.mode=checkid_setup&openid.claimed_id=http%3A%2F%2Fspecs.openid.net%2Fauth%2F2.0%2Fidentifier_select&openid.ns=http%3A%2F%2Fspecs.openid.net%2Fauth%2F2.0&";
-const AMAZON_SUBMIT_URL: &str = "https://www.amazon.com/ap/signin/";
 const PASSWORD_MISSING_ALERT: &str = "auth-password-missing-alert";
 
-/// Check if a phone number is associated with an Amazon account
+/// An Amazon storefront with its own accounts and sign-in flow
+#[derive(Debug, PartialEq, Eq)]
+pub struct Marketplace {
+    /// Module name (e.g. "amazon_uk")
+    pub name: &'static str,
+    /// Storefront domain (e.g. "amazon.co.uk")
+    pub domain: &'static str,
+    /// `openid.assoc_handle` of the sign-in flow
+    pub assoc_handle: &'static str,
+    /// Country calling codes served by this marketplace
+    country_codes: &'static [&'static str],
+}
+
+impl Marketplace {
+    fn signin_url(&self) -> String {
+        format!(
+            "https://www.{domain}/ap/signin?openid.pape.max_auth_age=0&openid.return_to=https%3A%2F%2Fwww.{domain}%2F%3F_encoding%3DUTF8%26ref_%3Dnav_ya_signin&openid.identity=http%3A%2F%2Fspecs.openid.net%2Fauth%2F2.0%2Fidentifier_select&openid.assoc_handle={handle}&openid.mode=checkid_setup&openid.claimed_id=http%3A%2F%2Fspecs.openid.net%2Fauth%2F2.0%2Fidentifier_select&openid.ns=http%3A%2F%2Fspecs.openid.net%2Fauth%2F2.0&",
+            domain = self.domain,
+            handle = self.assoc_handle,
+        )
+    }
+
+    fn submit_url(&self) -> String {
+        format!("https://www.{}/ap/signin/", self.domain)
+    }
+
+    /// Empty result for this marketplace
+    pub fn result(&self) -> CheckResult {
+        CheckResult::new(self.name, self.domain, "login")
+    }
+}
+
+const fn marketplace(
+    name: &'static str,
+    domain: &'static str,
+    assoc_handle: &'static str,
+    country_codes: &'static [&'static str],
+) -> Marketplace {
+    Marketplace { name, domain, assoc_handle, country_codes }
+}
+
+/// Every supported marketplace; amazon.com comes first and is the fallback
+pub static MARKETPLACES: [Marketplace; 13] = [
+    marketplace("amazon", "amazon.com", "usflex", &["1"]),
+    marketplace("amazon_ca", "amazon.ca", "caflex", &["1"]),
+    marketplace("amazon_uk", "amazon.co.uk", "gbflex", &["44"]),
+    marketplace("amazon_de", "amazon.de", "deflex", &["49", "43", "41"]),
+    marketplace("amazon_fr", "amazon.fr", "frflex", &["33", "32", "352", "377"]),
+    marketplace("amazon_it", "amazon.it", "itflex", &["39", "378"]),
+    marketplace("amazon_es", "amazon.es", "esflex", &["34", "376"]),
+    marketplace("amazon_nl", "amazon.nl", "nlflex", &["31"]),
+    marketplace("amazon_jp", "amazon.co.jp", "jpflex", &["81"]),
+    marketplace("amazon_in", "amazon.in", "inflex", &["91"]),
+    marketplace("amazon_au", "amazon.com.au", "auflex", &["61"]),
+    marketplace("amazon_mx", "amazon.com.mx", "mxflex", &["52"]),
+    marketplace("amazon_br", "amazon.com.br", "brflex", &["55"]),
+];
+
+/// Canadian area codes within the North American numbering plan (+1)
+const CANADIAN_AREA_CODES: &[&str] = &[
+    "204", "226", "236", "249", "250", "263", "289", "306", "343", "354", "365", "367", "368", "382", "403",
+    "416", "418", "428", "431", "437", "438", "450", "468", "474", "506", "514", "519", "548", "579", "581",
+    "584", "587", "604", "613", "639", "647", "672", "683", "705", "709", "742", "753", "778", "780", "782",
+    "807", "819", "825", "867", "873", "879", "902", "905",
+];
+
+/// Marketplace holding accounts for a number from this country
+pub fn marketplace_for(phone: &str, country_code: &str) -> &'static Marketplace {
+    if country_code == "1" {
+        let canadian = CANADIAN_AREA_CODES.iter().any(|area| phone.starts_with(area));
+        return if canadian { &MARKETPLACES[1] } else { &MARKETPLACES[0] };
+    }
+    MARKETPLACES
+        .iter()
+        .find(|marketplace| marketplace.country_codes.contains(&country_code))
+        .unwrap_or(&MARKETPLACES[0])
+}
+
+/// Check if a phone number is associated with an account on an Amazon marketplace
 /// 
-/// This function attempts to sign in to Amazon using the phone number as an email.
+/// This function attempts to sign in to the marketplace using the phone number as an email.
 /// If Amazon prompts for a password, it indicates the phone number is associated with an account.
+/// A captcha or bot check served instead of either form is reported as blocked.
 /// 
 /// # Arguments
 /// 
+/// * `marketplace` - The marketplace to sign in to
 /// * `phone` - The phone number to check (without country code)
 /// * `country_code` - The country code for the phone number
 /// * `client` - HTTP client for making requests
 /// 
 /// # Returns
 /// 
-/// A `CheckResult` indicating whether the phone number exists on Amazon
-pub async fn check_amazon(phone: &str, country_code: &str, client: &Client) -> CheckResult {
+/// A `CheckResult` for the marketplace domain indicating whether the phone number exists on it
+pub async fn check_amazon(marketplace: &Marketplace, phone: &str, country_code: &str, client: &HttpClient) -> CheckResult {
     let user_agent = USER_AGENTS.chrome[rand::thread_rng().gen_range(0..USER_AGENTS.chrome.len())];
     
-    match client.get(AMAZON_SIGNIN_URL).header("User-Agent", user_agent).send().await {
+    match client.send(client.get(marketplace.signin_url()).header("User-Agent", user_agent)).await {
         Ok(response) => {
-            match response.text().await {
-                Ok(html_content) => {
-                    let mut form_data = extract_form_data(&html_content);
+            match client.read(response).await {
+                Ok(page) if let Some(challenge) = challenge::detect(&page.body) => {
+                    let evidence = client.evidence(&page, evidence::fields([("challenge", challenge.to_string())]));
+                    marketplace.result().with_challenge(challenge).with_evidence(evidence)
+                }
+                Ok(page) => {
+                    let mut form_data = extract_form_data(&page.body);
                     
                     // Set email field
                     form_data.insert("email".to_owned(), format!("{country_code}{phone}"));
                     
                     // Submit form
-                    match client.post(AMAZON_SUBMIT_URL)
-                        .form(&form_data)
-                        .send()
-                        .await {
+                    match client.send(client.post(marketplace.submit_url()).form(&form_data)).await {
                         Ok(response) => {
-                            match response.text().await {
-                                Ok(html) => {
-                                    let exists = html.contains(PASSWORD_MISSING_ALERT);
-                                    CheckResult::new("amazon", "amazon.com", "login").with_exists(exists)
+                            match client.read(response).await {
+                                Ok(page) => {
+                                    let password_prompt = page.body.contains(PASSWORD_MISSING_ALERT);
+                                    let challenge = challenge::detect(&page.body);
+                                    let result = match challenge {
+                                        _ if password_prompt => marketplace.result().with_exists(true),
+                                        Some(challenge) => marketplace.result().with_challenge(challenge),
+                                        None => marketplace.result().with_exists(false),
+                                    };
+                                    let mut fields = evidence::fields([("password_prompt", password_prompt.to_string())]);
+                                    if let Some(challenge) = challenge {
+                                        fields.insert("challenge".to_owned(), challenge.to_string());
+                                    }
+                                    result.with_evidence(client.evidence(&page, fields))
                                 }
-                                Err(_) => CheckResult::new("amazon", "amazon.com", "login").with_rate_limit()
+                                Err(e) => marketplace.result().with_request_error(&e)
                             }
                         }
-                        Err(_) => CheckResult::new("amazon", "amazon.com", "login").with_rate_limit()
+                        Err(e) => marketplace.result().with_http_error(&e)
                     }
                 }
-                Err(_) => CheckResult::new("amazon", "amazon.com", "login").with_rate_limit()
+                Err(e) => marketplace.result().with_request_error(&e)
             }
         }
-        Err(_) => CheckResult::new("amazon", "amazon.com", "login").with_rate_limit()
+        Err(e) => marketplace.result().with_http_error(&e)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Outcome;
 
     #[test]
     fn test_extract_form_data() {
@@ -135,18 +227,49 @@ mod tests {
         assert!(!html_without_alert.contains(PASSWORD_MISSING_ALERT));
     }
 
+    #[test]
+    fn test_marketplace_for_country() {
+        assert_eq!(marketplace_for("7700900000", "44").domain, "amazon.co.uk");
+        assert_eq!(marketplace_for("644637111", "33").domain, "amazon.fr");
+        assert_eq!(marketplace_for("9012345678", "81").domain, "amazon.co.jp");
+        assert_eq!(marketplace_for("9876543210", "91").domain, "amazon.in");
+        // No local marketplace
+        assert_eq!(marketplace_for("612345678", "47").domain, "amazon.com");
+    }
+
+    #[test]
+    fn test_marketplace_for_north_america() {
+        assert_eq!(marketplace_for("4165550123", "1").domain, "amazon.ca");
+        assert_eq!(marketplace_for("2125550123", "1").domain, "amazon.com");
+    }
+
+    #[test]
+    fn test_marketplace_urls() {
+        let uk = marketplace_for("7700900000", "44");
+
+        assert!(uk.signin_url().starts_with("https://www.amazon.co.uk/ap/signin?"));
+        assert!(uk.signin_url().contains("openid.assoc_handle=gbflex"));
+        assert!(uk.signin_url().contains("return_to=https%3A%2F%2Fwww.amazon.co.uk%2F"));
+        assert_eq!(uk.submit_url(), "https://www.amazon.co.uk/ap/signin/");
+        assert_eq!(uk.result().name, "amazon_uk");
+    }
+
     #[tokio::test]
     async fn test_check_amazon_network_error() {
         // Use a client with an invalid URL to simulate network failure
-        let client = Client::builder()
+        let client = reqwest::Client::builder()
             .timeout(std::time::Duration::from_millis(1))
             .build()
-            .unwrap();
+            .unwrap()
+            .into();
         
-        let result = check_amazon("123456789", "33", &client).await;
+        let result = check_amazon(&MARKETPLACES[0], "123456789", "33", &client).await;
 
         assert_eq!(result.name, "amazon");
-        assert!(result.rate_limit);
+        assert_eq!(result.domain, "amazon.com");
+        assert_eq!(result.outcome, Outcome::Error);
+        assert!(result.error.is_some());
+        assert!(!result.rate_limit);
         assert!(!result.exists);
     }
 }
\ No newline at end of file
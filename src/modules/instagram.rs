@@ -1,5 +1,5 @@
-use crate::CheckResult;
-use reqwest::Client;
+use crate::{CheckResult, challenge, evidence, http::HttpClient};
+use serde::Deserialize;
 use serde_json::Value;
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
@@ -36,8 +36,65 @@ fn generate_data(phone_number_raw: &str) -> String {
 
 const INSTAGRAM_USER_AGENT: &str = "Instagram 101.0.0.15.120";
 const NO_USERS_FOUND_MSG: &str = "No users found";
+const FEEDBACK_REQUIRED_MSG: &str = "feedback_required";
+const PLEASE_WAIT_MSG: &str = "Please wait a few minutes";
+const INVALID_USER_ERROR: &str = "invalid_user";
+const RATE_LIMIT_ERRORS: &[&str] = &["rate_limit_error", "sentry_block"];
+/// Lookup fields kept as evidence
+const EVIDENCE_FIELDS: &[&str] = &["status", "message", "error_type", "spam"];
 
-pub async fn check_instagram(phone: &str, country_code: &str, client: &Client) -> CheckResult {
+/// Fields of a `users/lookup` answer that decide the outcome
+#[derive(Debug, Default, Deserialize)]
+struct LookupResponse {
+    #[serde(default)]
+    status: Option<String>,
+    #[serde(default)]
+    error_type: Option<String>,
+    #[serde(default)]
+    message: Option<String>,
+    #[serde(default)]
+    user: Option<Value>,
+    #[serde(default)]
+    spam: bool,
+}
+
+/// Classify a `users/lookup` response body
+///
+/// - a `user` object means the number is registered
+/// - `"No users found"` (or an `invalid_user` error) means it is not
+/// - spam flags, `feedback_required` and throttling errors are rate limits
+/// - checkpoint and login walls are challenges
+/// - any other failure, or a success without a user, is unexpected
+fn classify(body: &str) -> CheckResult {
+    let result = CheckResult::new("instagram", "instagram.com", "other");
+    if let Some(challenge) = challenge::detect(body) {
+        return result.with_challenge(challenge);
+    }
+    let Ok(response) = serde_json::from_str::<LookupResponse>(body) else {
+        return result.with_unexpected("lookup response is not JSON");
+    };
+
+    let message = response.message.as_deref().unwrap_or_default();
+    let error_type = response.error_type.as_deref().unwrap_or_default();
+    if response.user.as_ref().is_some_and(Value::is_object) {
+        result.with_exists(true)
+    } else if message == NO_USERS_FOUND_MSG || error_type == INVALID_USER_ERROR {
+        result.with_exists(false)
+    } else if response.spam
+        || message == FEEDBACK_REQUIRED_MSG
+        || message.starts_with(PLEASE_WAIT_MSG)
+        || RATE_LIMIT_ERRORS.contains(&error_type)
+    {
+        result.with_rate_limit()
+    } else if response.status.as_deref() == Some("fail") {
+        let reason = [message, error_type].into_iter().find(|s| !s.is_empty()).unwrap_or("lookup failed");
+        result.with_unexpected(format!("lookup failed: {reason}"))
+    } else {
+        result.with_unexpected("unexpected lookup response")
+    }
+}
+
+pub async fn check_instagram(phone: &str, country_code: &str, client: &HttpClient) -> CheckResult {
     let phone_number = format!("{country_code}{phone}");
     let data = generate_signature(&generate_data(&phone_number));
     
@@ -55,26 +112,27 @@ pub async fn check_instagram(phone: &str, country_code: &str, client: &Client) -
         request = request.header(*key, *value);
     }
     
-    match request.body(data).send().await {
+    match client.send(request.body(data)).await {
         Ok(response) => {
-            match response.json::<Value>().await {
-                Ok(json) => {
-                    let exists = json.get("message")
-                        .and_then(|msg| msg.as_str()) != Some(NO_USERS_FOUND_MSG);
-                    
-                    CheckResult::new("instagram", "instagram.com", "other").with_exists(exists)
+            match client.read(response).await {
+                Ok(page) => {
+                    let evidence = client.evidence(&page, evidence::json_fields(&page.body, EVIDENCE_FIELDS));
+                    classify(&page.body).with_evidence(evidence)
                 }
-                Err(_) => CheckResult::new("instagram", "instagram.com", "other").with_rate_limit()
+                Err(e) => CheckResult::new("instagram", "instagram.com", "other").with_request_error(&e)
             }
         }
-        Err(_) => CheckResult::new("instagram", "instagram.com", "other").with_rate_limit()
+        Err(e) => CheckResult::new("instagram", "instagram.com", "other").with_http_error(&e)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use serde_json::json;
+    use crate::Outcome;
+    use crate::retry::{RetryPolicy, with_retry};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
 
     #[test]
     fn test_generate_signature() {
@@ -98,43 +156,104 @@ mod tests {
         assert_eq!(parsed["ig_sig_key_version"], "4");
     }
 
-    #[tokio::test]
-    async fn test_check_instagram_no_users_found() {
-        // Note: This test checks the logic but doesn't use mock server
-        // since the function uses hardcoded URLs. In a real implementation,
-        // we'd want to make URLs configurable for testing.
-        
-        // Test the logic directly
-        let phone = "123456789";
-        let country_code = "33";
-        let phone_number = format!("{country_code}{phone}");
+    #[test]
+    fn test_check_instagram_no_users_found() {
+        let phone_number = format!("{}{}", "33", "123456789");
         let data = generate_data(&phone_number);
         let parsed: serde_json::Value = serde_json::from_str(&data).unwrap();
         
         assert_eq!(parsed["q"], phone_number);
         
-        // Test the response parsing logic
-        let json_response = json!({"message": "No users found"});
-        let exists = json_response.get("message")
-            .and_then(|msg| msg.as_str()) != Some("No users found");
-        assert!(!exists);
+        let result = classify(r#"{"message": "No users found", "status": "fail"}"#);
+        assert_eq!(result.outcome, Outcome::NotFound);
+        assert!(!result.exists);
     }
 
     #[test]
     fn test_response_parsing_user_exists() {
-        // Test the response parsing logic for user exists
-        let json_response = json!({"users": [{"username": "test"}]});
-        let exists = json_response.get("message")
-            .and_then(|msg| msg.as_str()) != Some("No users found");
-        assert!(exists);
+        let body = r#"{
+            "email_sent": false,
+            "sms_sent": false,
+            "lookup_source": "phone",
+            "user": {"pk": 1, "username": "test", "full_name": "", "is_private": false},
+            "status": "ok"
+        }"#;
+
+        let result = classify(body);
+
+        assert_eq!(result.outcome, Outcome::Found);
+        assert!(result.exists);
     }
 
     #[test]
     fn test_response_parsing_empty_response() {
-        // Test the response parsing logic for empty response
-        let json_response = json!({});
-        let exists = json_response.get("message")
-            .and_then(|msg| msg.as_str()) != Some("No users found");
-        assert!(exists);
+        // An empty object says nothing about the number
+        let result = classify("{}");
+
+        assert_eq!(result.outcome, Outcome::Unexpected);
+        assert!(!result.exists);
+        assert_eq!(classify(r#"{"status": "ok"}"#).outcome, Outcome::Unexpected);
+    }
+
+    #[test]
+    fn test_response_parsing_rate_limited() {
+        let spam = r#"{"message": "Please wait a few minutes before you try again.", "spam": true, "status": "fail"}"#;
+        let feedback = r#"{"message": "feedback_required", "feedback_title": "Try Again Later", "status": "fail"}"#;
+        let throttled = r#"{"message": "", "error_type": "rate_limit_error", "status": "fail"}"#;
+
+        for body in [spam, feedback, throttled] {
+            let result = classify(body);
+            assert_eq!(result.outcome, Outcome::RateLimited, "{body}");
+            assert!(!result.exists);
+        }
+    }
+
+    #[test]
+    fn test_response_parsing_checkpoint() {
+        let body = r#"{"message": "checkpoint_required", "checkpoint_url": "https://i.instagram.com/challenge/", "lock": false, "status": "fail"}"#;
+
+        let result = classify(body);
+
+        assert_eq!(result.outcome, Outcome::Blocked);
+        assert_eq!(result.challenge, Some(crate::Challenge::LoginWall));
+    }
+
+    #[test]
+    fn test_response_parsing_failure() {
+        let result = classify(r#"{"message": "Sorry, there was a problem with your request.", "status": "fail"}"#);
+
+        assert_eq!(result.outcome, Outcome::Unexpected);
+        assert!(result.error.unwrap().contains("problem with your request"));
+        assert_eq!(classify(r#"{"error_type": "invalid_user", "status": "fail"}"#).outcome, Outcome::NotFound);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_unexpected_responses_are_not_retried() {
+        let policy = RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(1),
+            jitter: 0.0,
+        };
+
+        for body in [r#"{"message": "Sorry, there was a problem with your request.", "status": "fail"}"#, "{}"] {
+            let calls = AtomicU32::new(0);
+            let result = with_retry(policy, || async {
+                calls.fetch_add(1, Ordering::SeqCst);
+                classify(body)
+            })
+            .await;
+
+            assert_eq!(result.attempts, 1, "{body}");
+            assert_eq!(calls.load(Ordering::SeqCst), 1, "{body}");
+        }
+    }
+
+    #[test]
+    fn test_response_parsing_not_json() {
+        let result = classify("<html>Oops</html>");
+
+        assert_eq!(result.outcome, Outcome::Unexpected);
+        assert!(!result.rate_limit);
+    }
+}
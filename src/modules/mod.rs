@@ -1,13 +1,108 @@
 //! Platform checking modules
-//! 
+//!
 //! Each module implements a specific platform checker for determining
 //! if a phone number is associated with an account on that platform.
 
+use std::sync::Arc;
+
+use crate::{CheckResult, VERSION};
+use crate::config::ProcessConfig;
+use crate::http::HttpClient;
+use crate::plugins::process;
+use crate::plugins::wasm::{self, WasmPlugin};
+use crate::sites::{self, Site};
+
 /// Amazon account checking via login form analysis
 pub mod amazon;
 
+use amazon::Marketplace;
+
 /// Instagram account checking via internal API
 pub mod instagram;
 
 /// Snapchat account checking via registration validation
-pub mod snapchat;
\ No newline at end of file
+pub mod snapchat;
+
+/// A checker that can be run against a phone number
+#[derive(Debug, Clone)]
+pub enum Module {
+    /// Amazon sign-in flow of one marketplace
+    Amazon(&'static Marketplace),
+    Instagram,
+    Snapchat,
+    /// Declarative site definition loaded from a signed bundle
+    Site(Arc<Site>),
+    /// Sandboxed WebAssembly plugin
+    Wasm(Arc<WasmPlugin>),
+    /// External executable speaking JSON over stdin/stdout
+    Process(Arc<ProcessConfig>),
+}
+
+impl Module {
+    /// Name used to refer to this module in the configuration
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Amazon(marketplace) => marketplace.name,
+            Self::Instagram => "instagram",
+            Self::Snapchat => "snapchat",
+            Self::Site(site) => &site.definition.name,
+            Self::Wasm(plugin) => &plugin.manifest.name,
+            Self::Process(plugin) => &plugin.name,
+        }
+    }
+
+    /// Version recorded in saved results: the tool's for built-in modules, the
+    /// bundle's for site definitions and the plugin's own, if it declares one
+    pub fn version(&self) -> Option<&str> {
+        match self {
+            Self::Amazon(_) | Self::Instagram | Self::Snapchat => Some(VERSION),
+            Self::Site(site) => Some(&site.bundle_version),
+            Self::Wasm(plugin) => plugin.manifest.version.as_deref(),
+            Self::Process(plugin) => plugin.version.as_deref(),
+        }
+    }
+
+    /// Empty result naming this module's platform, for checks that never ran to completion
+    pub fn result(&self) -> CheckResult {
+        match self {
+            Self::Amazon(marketplace) => marketplace.result(),
+            Self::Instagram => CheckResult::new("instagram", "instagram.com", "other"),
+            Self::Snapchat => CheckResult::new("snapchat", "snapchat.com", "register"),
+            Self::Site(site) => site.result(),
+            Self::Wasm(plugin) => plugin.manifest.result(),
+            Self::Process(plugin) => process::result(plugin),
+        }
+    }
+
+    /// Whether this module's requests go through the shared HTTP client (process plugins send their own)
+    pub fn uses_http(&self) -> bool {
+        !matches!(self, Self::Process(_))
+    }
+
+    /// Whether this module applies to a number (Amazon only checks the number's marketplace)
+    pub fn supports(&self, phone: &str, country_code: &str) -> bool {
+        match self {
+            Self::Amazon(marketplace) => amazon::marketplace_for(phone, country_code) == *marketplace,
+            _ => true,
+        }
+    }
+
+    /// Run this module's check
+    pub async fn check(&self, phone: &str, country_code: &str, client: &HttpClient) -> CheckResult {
+        match self {
+            Self::Amazon(marketplace) => amazon::check_amazon(marketplace, phone, country_code, client).await,
+            Self::Instagram => instagram::check_instagram(phone, country_code, client).await,
+            Self::Snapchat => snapchat::check_snapchat(phone, country_code, client).await,
+            Self::Site(site) => sites::check_site(site, phone, country_code, client).await,
+            Self::Wasm(plugin) => wasm::check_plugin(plugin, phone, country_code, client).await,
+            Self::Process(plugin) => process::check_process(plugin, phone, country_code).await,
+        }
+    }
+}
+
+/// Built-in modules; see [`Module::supports`] for which run for a given number
+pub fn builtin() -> Vec<Module> {
+    let mut modules: Vec<Module> = amazon::MARKETPLACES.iter().map(Module::Amazon).collect();
+    modules.extend([Module::Instagram, Module::Snapchat]);
+    modules
+}
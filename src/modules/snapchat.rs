@@ -1,5 +1,4 @@
-use crate::{CheckResult, user_agents::USER_AGENTS};
-use reqwest::Client;
+use crate::{CheckResult, challenge, evidence, http::HttpClient, user_agents::USER_AGENTS};
 use serde_json::Value;
 use std::collections::HashMap;
 use rand::Rng;
@@ -9,9 +8,63 @@ const SNAPCHAT_VALIDATE_URL: &str = "https://accounts.snapchat.com/accounts/vali
 const XSRF_TOKEN_COOKIE: &str = "xsrf_token";
 const TAKEN_NUMBER_STATUS: &str = "TAKEN_NUMBER";
 const OK_STATUS: &str = "OK";
+/// `status_code` values rejecting the number itself
+const INVALID_NUMBER_STATUSES: &[&str] = &[
+    "INVALID_NUMBER",
+    "INVALID_PHONE_NUMBER",
+    "INVALID_PHONE_NUMBER_FORMAT",
+    "PHONE_NUMBER_TOO_SHORT",
+    "PHONE_NUMBER_TOO_LONG",
+    "UNSUPPORTED_COUNTRY",
+    "UNSUPPORTED_COUNTRY_CODE",
+    "UNSUPPORTED_PHONE_NUMBER",
+];
+/// `status_code` values refusing to answer for now
+const RATE_LIMITED_STATUSES: &[&str] = &["RATE_LIMITED", "TOO_MANY_REQUESTS", "TOO_MANY_ATTEMPTS", "TRY_AGAIN_LATER"];
+/// `status_code` values rejecting the request rather than the number
+const REQUEST_ERROR_STATUSES: &[&str] = &["INVALID_XSRF_TOKEN", "INVALID_REQUEST", "INTERNAL_ERROR", "UNKNOWN_ERROR"];
 
-pub async fn check_snapchat(phone: &str, country_code: &str, client: &Client) -> CheckResult {
+/// Map a `validate_phone_number` answer to a result
+///
+/// Snapchat does not document `validate_phone_number`. `TAKEN_NUMBER` and `OK`
+/// are the answers the original Python ignorant (megadose/ignorant) relies on;
+/// the status lists above gather the other `status_code` values seen from the
+/// accounts.snapchat.com sign-up form and are not exhaustive.
+///
+/// Unknown statuses, and answers rejecting the request itself, are reported
+/// as unexpected keeping the raw value, so a new status shows up in the
+/// results instead of passing for a rate limit or being retried. Unknown
+/// statuses are also logged, so they can be added to the lists.
+fn classify(json: &Value) -> CheckResult {
+    let result = CheckResult::new("snapchat", "snapchat.com", "register");
+    let Some(status) = json.get("status_code").and_then(|s| s.as_str()) else {
+        return result.with_unexpected("response without status_code");
+    };
+
+    match status {
+        TAKEN_NUMBER_STATUS => result.with_exists(true),
+        OK_STATUS => result.with_exists(false),
+        status if INVALID_NUMBER_STATUSES.contains(&status) => {
+            result.with_invalid_input(format!("number rejected ({status})"))
+        }
+        status if RATE_LIMITED_STATUSES.contains(&status) => result.with_rate_limit(),
+        status if REQUEST_ERROR_STATUSES.contains(&status) => {
+            result.with_unexpected(format!("request rejected ({status})"))
+        }
+        status => {
+            eprintln!("Warning: snapchat answered with unknown status_code {status}");
+            result.with_unexpected(format!("unknown status_code {status}"))
+        }
+    }
+}
+
+pub async fn check_snapchat(phone: &str, country_code: &str, client: &HttpClient) -> CheckResult {
     let convert_to_country_code = get_country_code_map();
+    // No point fetching a token for a country Snapchat cannot validate
+    let Some(&country_code_str) = convert_to_country_code.get(country_code) else {
+        return CheckResult::new("snapchat", "snapchat.com", "register")
+            .with_invalid_input(format!("country code +{country_code} is not supported"));
+    };
     let user_agent = USER_AGENTS.chrome[rand::thread_rng().gen_range(0..USER_AGENTS.chrome.len())];
     
     let headers = [
@@ -32,60 +85,58 @@ pub async fn check_snapchat(phone: &str, country_code: &str, client: &Client) ->
         request = request.header(*key, *value);
     }
     
-    match request.send().await {
+    match client.send(request).await {
         Ok(response) => {
-            let mut cookies = response.cookies();
-            let xsrf_token = cookies
+            let xsrf_token = response
+                .cookies()
                 .find(|cookie| cookie.name() == XSRF_TOKEN_COOKIE)
                 .map(|cookie| cookie.value().to_owned());
             
             if let Some(xsrf_token) = xsrf_token {
-                if let Some(&country_code_str) = convert_to_country_code.get(country_code) {
-                    let mut form_data = HashMap::new();
-                    form_data.insert("phone_country_code", country_code_str);
-                    form_data.insert("phone_number", phone);
-                    form_data.insert("xsrf_token", &xsrf_token);
-                    
-                    let mut request = client.post(SNAPCHAT_VALIDATE_URL);
-                    for (key, value) in &headers {
-                        request = request.header(*key, *value);
-                    }
-                    
-                    match request.form(&form_data).send().await {
-                        Ok(response) => {
-                            match response.json::<Value>().await {
-                                Ok(json) => {
-                                    let status = json.get("status_code").and_then(|s| s.as_str());
-                                    
-                                    match status {
-                                        Some(TAKEN_NUMBER_STATUS) => {
-                                            CheckResult::new("snapchat", "snapchat.com", "register").with_exists(true)
-                                        }
-                                        Some(OK_STATUS) => {
-                                            CheckResult::new("snapchat", "snapchat.com", "register").with_exists(false)
-                                        }
-                                        _ => {
-                                            CheckResult::new("snapchat", "snapchat.com", "register").with_rate_limit()
-                                        }
-                                    }
-                                }
-                                Err(_) => CheckResult::new("snapchat", "snapchat.com", "register").with_rate_limit()
-                            }
-                        }
-                        Err(_) => CheckResult::new("snapchat", "snapchat.com", "register").with_rate_limit()
+                let mut form_data = HashMap::new();
+                form_data.insert("phone_country_code", country_code_str);
+                form_data.insert("phone_number", phone);
+                form_data.insert("xsrf_token", &xsrf_token);
+                
+                let mut request = client.post(SNAPCHAT_VALIDATE_URL);
+                for (key, value) in &headers {
+                    request = request.header(*key, *value);
+                }
+                
+                match client.send(request.form(&form_data)).await {
+                    Ok(response) => {
+                        let page = match client.read(response).await {
+                            Ok(page) => page,
+                            Err(e) => return CheckResult::new("snapchat", "snapchat.com", "register").with_request_error(&e),
+                        };
+                        let evidence = client.evidence(&page, evidence::json_fields(&page.body, &["status_code"]));
+                        let result = match challenge::detect(&page.body) {
+                            Some(challenge) => CheckResult::new("snapchat", "snapchat.com", "register").with_challenge(challenge),
+                            None => match serde_json::from_str::<Value>(&page.body) {
+                                Ok(json) => classify(&json),
+                                Err(_) => CheckResult::new("snapchat", "snapchat.com", "register")
+                                    .with_unexpected("validate response is not JSON"),
+                            },
+                        };
+                        result.with_evidence(evidence)
                     }
-                } else {
-                    CheckResult::new("snapchat", "snapchat.com", "register").with_rate_limit()
+                    Err(e) => CheckResult::new("snapchat", "snapchat.com", "register").with_http_error(&e)
                 }
             } else {
-                CheckResult::new("snapchat", "snapchat.com", "register").with_rate_limit()
+                // No token usually means a challenge page was served instead of the home page
+                match response.text().await.ok().as_deref().and_then(challenge::detect) {
+                    Some(challenge) => CheckResult::new("snapchat", "snapchat.com", "register").with_challenge(challenge),
+                    None => CheckResult::new("snapchat", "snapchat.com", "register")
+                        .with_unexpected("home page set no xsrf_token cookie"),
+                }
             }
         }
-        Err(_) => CheckResult::new("snapchat", "snapchat.com", "register").with_rate_limit()
+        Err(e) => CheckResult::new("snapchat", "snapchat.com", "register").with_http_error(&e)
     }
 }
 
-fn get_country_code_map() -> HashMap<&'static str, &'static str> {
+/// Country Snapchat files each calling code under
+pub fn get_country_code_map() -> HashMap<&'static str, &'static str> {
     let mut map = HashMap::new();
     map.insert("1", "VI");
     map.insert("49", "DE");
@@ -298,6 +349,7 @@ fn get_country_code_map() -> HashMap<&'static str, &'static str> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::Outcome;
     use serde_json::json;
 
     #[test]
@@ -313,31 +365,48 @@ mod tests {
 
     #[test]
     fn test_status_code_parsing() {
-        // Test status code parsing logic
-        let taken_response = json!({"status_code": "TAKEN_NUMBER"});
-        let ok_response = json!({"status_code": "OK"});
-        let rate_limited_response = json!({"status_code": "RATE_LIMITED"});
-        let unknown_response = json!({"status_code": "UNKNOWN"});
-        
-        let taken_status = taken_response.get("status_code").and_then(|s| s.as_str());
-        let ok_status = ok_response.get("status_code").and_then(|s| s.as_str());
-        let rate_limited_status = rate_limited_response.get("status_code").and_then(|s| s.as_str());
-        let unknown_status = unknown_response.get("status_code").and_then(|s| s.as_str());
-        
-        assert_eq!(taken_status, Some(TAKEN_NUMBER_STATUS));
-        assert_eq!(ok_status, Some(OK_STATUS));
-        assert_eq!(rate_limited_status, Some("RATE_LIMITED"));
-        assert_eq!(unknown_status, Some("UNKNOWN"));
+        let taken = classify(&json!({"status_code": "TAKEN_NUMBER"}));
+        let ok = classify(&json!({"status_code": "OK"}));
+        let rate_limited = classify(&json!({"status_code": "RATE_LIMITED"}));
+
+        assert_eq!(taken.outcome, Outcome::Found);
+        assert!(taken.exists);
+        assert_eq!(ok.outcome, Outcome::NotFound);
+        assert!(!ok.exists);
+        assert_eq!(rate_limited.outcome, Outcome::RateLimited);
+        assert!(rate_limited.rate_limit);
+    }
+
+    #[test]
+    fn test_status_code_invalid_number() {
+        for status in ["INVALID_NUMBER", "INVALID_PHONE_NUMBER", "UNSUPPORTED_COUNTRY"] {
+            let result = classify(&json!({"status_code": status}));
+
+            assert_eq!(result.outcome, Outcome::InvalidInput, "{status}");
+            assert!(!result.rate_limit);
+            assert!(result.error.unwrap().contains(status));
+        }
+    }
+
+    #[test]
+    fn test_status_code_unknown_kept() {
+        let result = classify(&json!({"status_code": "SOMETHING_NEW"}));
+
+        assert_eq!(result.outcome, Outcome::Unexpected);
+        assert!(!result.outcome.is_transient());
+        assert!(result.error.unwrap().contains("SOMETHING_NEW"));
+        assert_eq!(classify(&json!({"status": "ok"})).outcome, Outcome::Unexpected);
+        assert_eq!(classify(&json!({"status_code": "INVALID_XSRF_TOKEN"})).outcome, Outcome::Unexpected);
     }
 
     #[tokio::test]
     async fn test_check_snapchat_invalid_country_code() {
-        let client = Client::new();
+        let client = reqwest::Client::new().into();
         let result = check_snapchat("123456789", "999", &client).await;
 
         assert_eq!(result.name, "snapchat");
         assert!(!result.exists);
-        assert!(result.rate_limit);
+        assert!(!result.rate_limit);
+        assert_eq!(result.outcome, Outcome::InvalidInput);
     }
-
-}
\ No newline at end of file
+}
@@ -0,0 +1,125 @@
+//! External checker plugins
+//!
+//! Plugins cover platforms whose checks need logic a site definition cannot
+//! express. Each plugin is described by a manifest or config entry naming the
+//! platform it checks, so a plugin that fails still shows up in the results.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{Challenge, CheckResult};
+
+/// External-process plugins speaking JSON over stdin/stdout
+pub mod process;
+
+/// Sandboxed WebAssembly plugins
+pub mod wasm;
+
+/// Plugin manifest (`<name>.toml`)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginManifest {
+    /// Platform name (e.g. "example")
+    pub name: String,
+    /// Platform domain (e.g. "example.com")
+    pub domain: String,
+    /// Method used for checking (e.g. "login", "register", "other")
+    pub method: String,
+    /// Whether this platform frequently rate limits requests
+    #[serde(default)]
+    pub frequent_rate_limit: bool,
+    /// Hosts the plugin may send HTTP requests to
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+    /// Plugin version, recorded in saved results
+    #[serde(default)]
+    pub version: Option<String>,
+}
+
+impl PluginManifest {
+    /// Empty result for this plugin's platform
+    pub fn result(&self) -> CheckResult {
+        let mut result = CheckResult::new(&self.name, &self.domain, &self.method);
+        result.frequent_rate_limit = self.frequent_rate_limit;
+        result
+    }
+}
+
+/// The target number in the formats plugins commonly need
+#[derive(Debug, Clone, Serialize)]
+pub struct PhoneNumber {
+    /// National number as given on the command line (e.g. "644637111")
+    pub phone: String,
+    /// Country calling code without `+` (e.g. "33")
+    pub country_code: String,
+    /// E.164 form (e.g. "+33644637111")
+    pub e164: String,
+    /// Country code and national number without separators (e.g. "33644637111")
+    pub digits: String,
+}
+
+impl PhoneNumber {
+    pub fn new(phone: &str, country_code: &str) -> Self {
+        Self {
+            phone: phone.to_owned(),
+            country_code: country_code.to_owned(),
+            e164: format!("+{country_code}{phone}"),
+            digits: format!("{country_code}{phone}"),
+        }
+    }
+}
+
+/// Answer returned by a plugin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PluginOutcome {
+    Found,
+    NotFound,
+    RateLimited,
+    Blocked,
+    Error,
+}
+
+impl PluginOutcome {
+    /// Apply this outcome to the plugin's result
+    pub fn apply(self, result: CheckResult) -> CheckResult {
+        match self {
+            Self::Found => result.with_exists(true),
+            Self::NotFound => result.with_exists(false),
+            Self::RateLimited => result.with_rate_limit(),
+            Self::Blocked => result.with_challenge(Challenge::Other),
+            Self::Error => result.with_error("plugin reported an error"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Outcome;
+
+    #[test]
+    fn test_phone_number_formats() {
+        let number = PhoneNumber::new("644637111", "33");
+
+        assert_eq!(number.e164, "+33644637111");
+        assert_eq!(number.digits, "33644637111");
+    }
+
+    #[test]
+    fn test_outcome_apply() {
+        let manifest: PluginManifest = toml::from_str(
+            r#"
+            name = "example"
+            domain = "example.com"
+            method = "register"
+            "#,
+        )
+        .unwrap();
+
+        assert!(PluginOutcome::Found.apply(manifest.result()).exists);
+        assert!(!PluginOutcome::NotFound.apply(manifest.result()).exists);
+        assert!(PluginOutcome::RateLimited.apply(manifest.result()).rate_limit);
+        assert_eq!(PluginOutcome::Blocked.apply(manifest.result()).outcome, Outcome::Blocked);
+        assert_eq!(PluginOutcome::Error.apply(manifest.result()).outcome, Outcome::Error);
+    }
+}
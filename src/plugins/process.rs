@@ -0,0 +1,257 @@
+//! External-process checker plugins
+//!
+//! A process plugin is any executable configured under `[[plugins.process]]`.
+//! For each check the CLI launches it, writes one JSON object to its stdin and
+//! reads one JSON object back from its stdout.
+//!
+//! ## Protocol
+//!
+//! Input (stdin): the [`PhoneNumber`] fields (`phone`, `country_code`, `e164`,
+//! `digits`), the plugin's `settings` table and its `timeout_secs`.
+//!
+//! Output (stdout): a `CheckResult`-shaped object. Only `exists`,
+//! `rate_limit`, `challenge` and `error` are read; the platform name, domain and method
+//! always come from the configuration.
+//!
+//! Exit codes:
+//!
+//! - `0`: stdout holds the result
+//! - `75` (`EX_TEMPFAIL`): the platform rate limited the check; stdout is ignored
+//! - anything else: the check failed
+//!
+//! Plugins that overrun their timeout are killed. Stderr is kept up to
+//! `max_stderr_bytes` and shown when the plugin fails.
+
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow, bail};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+
+use super::PhoneNumber;
+use crate::{Challenge, CheckResult};
+use crate::config::ProcessConfig;
+
+/// Exit code signalling the platform rate limited the plugin
+pub const EXIT_RATE_LIMITED: i32 = 75;
+
+/// Largest answer read from a plugin's stdout
+const MAX_STDOUT_BYTES: usize = 64 * 1024;
+
+#[derive(Serialize)]
+struct ProcessInput<'a> {
+    #[serde(flatten)]
+    number: PhoneNumber,
+    settings: &'a toml::Table,
+    timeout_secs: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProcessOutput {
+    #[serde(default)]
+    rate_limit: bool,
+    #[serde(default)]
+    exists: bool,
+    #[serde(default)]
+    challenge: Option<Challenge>,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+/// Read at most `cap` bytes, then drain the rest so the child never blocks on a full pipe
+async fn read_capped(mut reader: impl AsyncRead + Unpin, cap: usize) -> std::io::Result<Vec<u8>> {
+    let mut captured = Vec::new();
+    (&mut reader).take(cap as u64).read_to_end(&mut captured).await?;
+    tokio::io::copy(&mut reader, &mut tokio::io::sink()).await?;
+    Ok(captured)
+}
+
+async fn run(plugin: &ProcessConfig, input: &[u8]) -> Result<ProcessOutput> {
+    let mut child = Command::new(&plugin.command)
+        .args(&plugin.args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("failed to launch {}", plugin.command.display()))?;
+
+    let (Some(mut stdin), Some(stdout), Some(stderr)) = (child.stdin.take(), child.stdout.take(), child.stderr.take())
+    else {
+        bail!("failed to open pipes to {}", plugin.command.display());
+    };
+
+    let write = async move {
+        // A plugin may exit without reading its input; that is not an error
+        let _ = stdin.write_all(input).await;
+    };
+    let exchange = async {
+        let ((), stdout, stderr, status) = tokio::join!(
+            write,
+            read_capped(stdout, MAX_STDOUT_BYTES),
+            read_capped(stderr, plugin.max_stderr_bytes),
+            child.wait(),
+        );
+        Ok::<_, std::io::Error>((stdout?, stderr?, status?))
+    };
+
+    let timeout = Duration::from_secs(plugin.timeout_secs);
+    let (stdout, stderr, status) = match tokio::time::timeout(timeout, exchange).await {
+        Ok(exchange) => exchange?,
+        Err(_) => {
+            let _ = child.kill().await;
+            bail!("timed out after {}s", plugin.timeout_secs);
+        }
+    };
+
+    match status.code() {
+        Some(0) => serde_json::from_slice(&stdout).context("invalid plugin output"),
+        Some(EXIT_RATE_LIMITED) => Ok(ProcessOutput {
+            rate_limit: true,
+            exists: false,
+            challenge: None,
+            error: None,
+        }),
+        code => Err(anyhow!(
+            "exited with {}: {}",
+            code.map_or_else(|| "a signal".to_owned(), |code| format!("status {code}")),
+            String::from_utf8_lossy(&stderr).trim()
+        )),
+    }
+}
+
+/// Empty result for the plugin's platform, before it is launched
+pub fn result(plugin: &ProcessConfig) -> CheckResult {
+    let mut result = CheckResult::new(&plugin.name, &plugin.domain, &plugin.method);
+    result.frequent_rate_limit = plugin.frequent_rate_limit;
+    result
+}
+
+/// Check a phone number with an external-process plugin
+///
+/// Failures are reported on stderr and recorded as an error for the plugin's
+/// platform, like a failed request in the built-in modules.
+pub async fn check_process(plugin: &ProcessConfig, phone: &str, country_code: &str) -> CheckResult {
+    let result = result(plugin);
+
+    let input = ProcessInput {
+        number: PhoneNumber::new(phone, country_code),
+        settings: &plugin.settings,
+        timeout_secs: plugin.timeout_secs,
+    };
+    let input = match serde_json::to_vec(&input) {
+        Ok(input) => input,
+        Err(e) => return result.with_error(e.to_string()),
+    };
+
+    match run(plugin, &input).await {
+        Ok(ProcessOutput { error: Some(error), .. }) => result.with_error(error),
+        Ok(ProcessOutput { challenge: Some(challenge), .. }) => result.with_challenge(challenge),
+        Ok(ProcessOutput { rate_limit: true, .. }) => result.with_rate_limit(),
+        Ok(ProcessOutput { exists, .. }) => result.with_exists(exists),
+        Err(e) => {
+            eprintln!("Plugin {} failed: {e:#}", plugin.name);
+            result.with_error(format!("{e:#}"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Outcome;
+
+    fn plugin(script: &str) -> ProcessConfig {
+        toml::from_str(&format!(
+            r#"
+            name = "example"
+            domain = "example.com"
+            method = "register"
+            command = "sh"
+            args = ["-c", {script:?}]
+            timeout_secs = 1
+            settings = {{ region = "eu" }}
+            "#
+        ))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_process_found() {
+        let plugin = plugin(r#"cat >/dev/null; echo '{"exists": true, "rate_limit": false}'"#);
+
+        let result = check_process(&plugin, "644637111", "33").await;
+
+        assert_eq!(result.name, "example");
+        assert!(result.exists);
+        assert!(!result.rate_limit);
+    }
+
+    #[tokio::test]
+    async fn test_process_receives_number_and_settings() {
+        let plugin = plugin(
+            r#"input=$(cat); case "$input" in *'"e164":"+33644637111"'*'"region":"eu"'*) echo '{"exists": true}';; *) echo '{"exists": false}';; esac"#,
+        );
+
+        let result = check_process(&plugin, "644637111", "33").await;
+
+        assert!(result.exists);
+    }
+
+    #[tokio::test]
+    async fn test_process_rate_limited_exit_code() {
+        let plugin = plugin("exit 75");
+
+        let result = check_process(&plugin, "644637111", "33").await;
+
+        assert!(result.rate_limit);
+        assert!(!result.exists);
+    }
+
+    #[tokio::test]
+    async fn test_process_challenge() {
+        let plugin = plugin(r#"cat >/dev/null; echo '{"challenge": "captcha"}'"#);
+
+        let result = check_process(&plugin, "644637111", "33").await;
+
+        assert_eq!(result.outcome, Outcome::Blocked);
+        assert_eq!(result.challenge, Some(Challenge::Captcha));
+    }
+
+    #[tokio::test]
+    async fn test_process_failure() {
+        let plugin = plugin("echo boom >&2; exit 1");
+
+        let err = run(&plugin, b"{}").await.unwrap_err();
+
+        assert!(err.to_string().contains("status 1"));
+        assert!(err.to_string().contains("boom"));
+    }
+
+    #[tokio::test]
+    async fn test_process_timeout() {
+        let plugin = plugin("sleep 5");
+
+        let err = run(&plugin, b"{}").await.unwrap_err();
+
+        assert!(err.to_string().contains("timed out"));
+    }
+
+    #[tokio::test]
+    async fn test_process_invalid_output() {
+        let plugin = plugin("echo not json");
+
+        let result = check_process(&plugin, "644637111", "33").await;
+
+        assert_eq!(result.outcome, Outcome::Error);
+    }
+
+    #[tokio::test]
+    async fn test_read_capped() {
+        let captured = read_capped(&b"0123456789"[..], 4).await.unwrap();
+
+        assert_eq!(captured, b"0123");
+    }
+}
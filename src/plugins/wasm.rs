@@ -0,0 +1,555 @@
+//! Sandboxed WebAssembly checker plugins
+//!
+//! A plugin is a `<name>.wasm` module with a `<name>.toml` manifest beside it.
+//! Plugins run in an interpreter with a fuel budget, a memory cap and a
+//! wall-clock limit; a trap, an exhausted budget or a malformed answer only
+//! affects that plugin's result.
+//!
+//! ## ABI
+//!
+//! The plugin exports:
+//!
+//! - `memory`: its linear memory
+//! - `alloc(len: i32) -> i32`: reserve `len` bytes for the host to write into
+//! - `check(ptr: i32, len: i32) -> i64`: run the check on the JSON-encoded
+//!   [`PhoneNumber`] at `ptr` and return the location of a JSON object such as
+//!   `{"outcome": "found"}`, packed as `ptr << 32 | len`
+//!
+//! The host provides, in the `ignorant` import module:
+//!
+//! - `http_request(ptr: i32, len: i32) -> i64`: send the JSON-encoded
+//!   [`HttpRequest`] at `ptr` and return a packed pointer to the JSON-encoded
+//!   [`HttpResponse`]. Only hosts listed in the manifest's `allowed_hosts` can
+//!   be reached. Redirects are not followed: the plugin gets the 3xx response
+//!   and requests its `Location` itself, which is checked like any other URL.
+//! - `now_ms() -> i64`: milliseconds since the Unix epoch
+//!
+//! Once the wall-clock limit passes, both host functions trap, so a plugin
+//! still running after its check timed out stops at its next host call. wasmi
+//! cannot interrupt pure computation, which the fuel budget bounds instead.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result, anyhow, bail};
+use reqwest::{ClientBuilder, Method, redirect};
+use serde::{Deserialize, Serialize};
+use tokio::runtime::Handle;
+use wasmi::{Caller, Engine, Extern, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+use super::{PhoneNumber, PluginManifest, PluginOutcome};
+use crate::CheckResult;
+use crate::config::WasmConfig;
+use crate::http::{HttpClient, HttpError};
+
+/// Largest request or answer the host will read back from a plugin
+const MAX_OUTPUT_BYTES: usize = 64 * 1024;
+
+/// Have a client leave redirects to plugins, so every hop is checked against `allowed_hosts`
+pub fn sandboxed(builder: ClientBuilder) -> ClientBuilder {
+    builder.redirect(redirect::Policy::none())
+}
+
+/// A loaded, validated plugin
+#[derive(Debug)]
+pub struct WasmPlugin {
+    pub manifest: PluginManifest,
+    module: Module,
+    limits: WasmConfig,
+}
+
+/// Request a plugin asks the host to send
+#[derive(Debug, Clone, Deserialize)]
+pub struct HttpRequest {
+    #[serde(default = "default_method")]
+    pub method: String,
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub body: Option<String>,
+}
+
+fn default_method() -> String {
+    "GET".to_owned()
+}
+
+/// Response handed back to a plugin; `error` is set instead when the request
+/// was refused or failed
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl HttpResponse {
+    fn error(message: impl Into<String>) -> Self {
+        Self {
+            error: Some(message.into()),
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PluginOutput {
+    outcome: PluginOutcome,
+}
+
+/// Per-check state available to host functions
+struct HostState {
+    limits: StoreLimits,
+    client: HttpClient,
+    runtime: Handle,
+    allowed_hosts: Vec<String>,
+    requests_left: u32,
+    max_response_bytes: usize,
+    deadline: Instant,
+}
+
+impl HostState {
+    /// Check a request against the plugin's capabilities
+    fn authorize(&mut self, request: &HttpRequest) -> Result<(Method, url::Url), String> {
+        if self.requests_left == 0 {
+            return Err("HTTP request limit reached".to_owned());
+        }
+        if Instant::now() >= self.deadline {
+            return Err("check deadline exceeded".to_owned());
+        }
+        let method = Method::from_bytes(request.method.as_bytes()).map_err(|_| "invalid HTTP method".to_owned())?;
+        let url = url::Url::parse(&request.url).map_err(|e| format!("invalid URL: {e}"))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("scheme {} is not allowed", url.scheme()));
+        }
+        let host = url.host_str().unwrap_or_default();
+        if !self.allowed_hosts.iter().any(|allowed| allowed.eq_ignore_ascii_case(host)) {
+            return Err(format!("host {host} is not in the plugin's allowed_hosts"));
+        }
+        self.requests_left -= 1;
+        Ok((method, url))
+    }
+
+    fn fetch(&mut self, request: &HttpRequest) -> HttpResponse {
+        let (method, url) = match self.authorize(request) {
+            Ok(target) => target,
+            Err(message) => return HttpResponse::error(message),
+        };
+
+        let mut builder = self.client.request(method, url);
+        for (key, value) in &request.headers {
+            builder = builder.header(key, value);
+        }
+        if let Some(body) = &request.body {
+            builder = builder.body(body.clone());
+        }
+
+        let client = self.client.clone();
+        let max_body = self.max_response_bytes;
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        self.runtime.block_on(async move {
+            let exchange = async {
+                let mut response = client.execute(builder).await?;
+                let status = response.status().as_u16();
+                let headers = response
+                    .headers()
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), String::from_utf8_lossy(value.as_bytes()).into_owned()))
+                    .collect();
+                let mut body = Vec::new();
+                while body.len() < max_body {
+                    match response.chunk().await.map_err(HttpError::from)? {
+                        Some(chunk) => body.extend_from_slice(&chunk),
+                        None => break,
+                    }
+                }
+                body.truncate(max_body);
+                Ok::<_, HttpError>(HttpResponse {
+                    status,
+                    headers,
+                    body: String::from_utf8_lossy(&body).into_owned(),
+                    error: None,
+                })
+            };
+            match tokio::time::timeout(remaining, exchange).await {
+                Ok(Ok(response)) => response,
+                Ok(Err(e)) => HttpResponse::error(e.to_string()),
+                Err(_) => HttpResponse::error("check deadline exceeded"),
+            }
+        })
+    }
+}
+
+fn pack(ptr: i32, len: i32) -> i64 {
+    (i64::from(ptr as u32) << 32) | i64::from(len as u32)
+}
+
+fn unpack(value: i64) -> (usize, usize) {
+    ((value as u64 >> 32) as usize, (value as u64 & 0xffff_ffff) as usize)
+}
+
+fn guest_memory(caller: &Caller<'_, HostState>) -> Result<Memory, wasmi::Error> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| wasmi::Error::new("plugin does not export `memory`"))
+}
+
+/// Copy `bytes` into memory reserved through the plugin's `alloc` export
+fn write_guest(caller: &mut Caller<'_, HostState>, bytes: &[u8]) -> Result<i64, wasmi::Error> {
+    let memory = guest_memory(caller)?;
+    let alloc = caller
+        .get_export("alloc")
+        .and_then(Extern::into_func)
+        .ok_or_else(|| wasmi::Error::new("plugin does not export `alloc`"))?
+        .typed::<i32, i32>(&*caller)?;
+    let len = i32::try_from(bytes.len()).map_err(|_| wasmi::Error::new("response too large"))?;
+    let ptr = alloc.call(&mut *caller, len)?;
+    memory.write(&mut *caller, ptr as u32 as usize, bytes)?;
+    Ok(pack(ptr, len))
+}
+
+fn host_linker(engine: &Engine) -> Result<Linker<HostState>> {
+    let mut linker = Linker::new(engine);
+    linker.func_wrap(
+        "ignorant",
+        "http_request",
+        |mut caller: Caller<'_, HostState>, ptr: i32, len: i32| -> Result<i64, wasmi::Error> {
+            if Instant::now() >= caller.data().deadline {
+                return Err(wasmi::Error::new("check deadline exceeded"));
+            }
+            let memory = guest_memory(&caller)?;
+            if len as u32 as usize > MAX_OUTPUT_BYTES {
+                return Err(wasmi::Error::new("HTTP request exceeds size limit"));
+            }
+            let mut buffer = vec![0; len as u32 as usize];
+            memory.read(&caller, ptr as u32 as usize, &mut buffer)?;
+            let response = match serde_json::from_slice::<HttpRequest>(&buffer) {
+                Ok(request) => caller.data_mut().fetch(&request),
+                Err(e) => HttpResponse::error(format!("invalid request: {e}")),
+            };
+            let bytes = serde_json::to_vec(&response).map_err(|e| wasmi::Error::new(e.to_string()))?;
+            write_guest(&mut caller, &bytes)
+        },
+    )?;
+    linker.func_wrap("ignorant", "now_ms", |caller: Caller<'_, HostState>| -> Result<i64, wasmi::Error> {
+        if Instant::now() >= caller.data().deadline {
+            return Err(wasmi::Error::new("check deadline exceeded"));
+        }
+        Ok(SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as i64))
+    })?;
+    Ok(linker)
+}
+
+/// List the plugin modules in `dir`
+pub fn discover(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("failed to read plugin directory {}", dir.display()))? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "wasm") {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+impl WasmPlugin {
+    /// Load and validate `path` along with its manifest
+    pub fn load(engine: &Engine, path: &Path, limits: &WasmConfig) -> Result<Self> {
+        let manifest_path = path.with_extension("toml");
+        let manifest = fs::read_to_string(&manifest_path)
+            .with_context(|| format!("failed to read plugin manifest {}", manifest_path.display()))?;
+        let manifest: PluginManifest = toml::from_str(&manifest)
+            .with_context(|| format!("invalid plugin manifest {}", manifest_path.display()))?;
+
+        let bytes = fs::read(path).with_context(|| format!("failed to read plugin {}", path.display()))?;
+        let module = Module::new(engine, &bytes[..]).with_context(|| format!("invalid plugin {}", path.display()))?;
+        for export in ["memory", "alloc", "check"] {
+            if module.get_export(export).is_none() {
+                bail!("plugin {} does not export `{export}`", path.display());
+            }
+        }
+
+        Ok(Self {
+            manifest,
+            module,
+            limits: limits.clone(),
+        })
+    }
+
+    fn run(&self, input: &[u8], client: HttpClient, runtime: Handle, deadline: Instant) -> Result<PluginOutcome> {
+        let state = HostState {
+            limits: StoreLimitsBuilder::new()
+                .memory_size(self.limits.max_memory_mb * 1024 * 1024)
+                .build(),
+            client,
+            runtime,
+            allowed_hosts: self.manifest.allowed_hosts.clone(),
+            requests_left: self.limits.max_http_requests,
+            max_response_bytes: self.limits.max_response_bytes,
+            deadline,
+        };
+        let mut store = Store::new(self.module.engine(), state);
+        store.limiter(|state| &mut state.limits);
+        store.set_fuel(self.limits.fuel).map_err(|e| anyhow!("{e}"))?;
+
+        let instance = host_linker(self.module.engine())?
+            .instantiate(&mut store, &self.module)?
+            .start(&mut store)?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| anyhow!("plugin does not export `memory`"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")?;
+        let check = instance.get_typed_func::<(i32, i32), i64>(&store, "check")?;
+
+        let len = i32::try_from(input.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as u32 as usize, input).map_err(wasmi::Error::from)?;
+
+        let (out_ptr, out_len) = unpack(check.call(&mut store, (ptr, len))?);
+        if out_len > MAX_OUTPUT_BYTES {
+            bail!("plugin output exceeds {MAX_OUTPUT_BYTES} bytes");
+        }
+        let mut output = vec![0; out_len];
+        memory.read(&store, out_ptr, &mut output).map_err(wasmi::Error::from)?;
+
+        let output: PluginOutput = serde_json::from_slice(&output).context("invalid plugin output")?;
+        Ok(output.outcome)
+    }
+}
+
+/// Create the engine plugins are compiled for; fuel metering is always on
+pub fn engine() -> Engine {
+    let mut config = wasmi::Config::default();
+    config.consume_fuel(true);
+    Engine::new(&config)
+}
+
+/// Check a phone number with a WebAssembly plugin
+///
+/// The plugin runs on a blocking thread. Any failure (trap, exhausted fuel,
+/// timeout, malformed output) is reported as an error for the plugin's
+/// platform, like a failed request in the built-in modules.
+pub async fn check_plugin(plugin: &Arc<WasmPlugin>, phone: &str, country_code: &str, client: &HttpClient) -> CheckResult {
+    let result = plugin.manifest.result();
+    let input = match serde_json::to_vec(&PhoneNumber::new(phone, country_code)) {
+        Ok(input) => input,
+        Err(e) => return result.with_error(e.to_string()),
+    };
+
+    let timeout = Duration::from_secs(plugin.limits.timeout_secs);
+    let deadline = Instant::now() + timeout;
+    let task = tokio::task::spawn_blocking({
+        let plugin = Arc::clone(plugin);
+        let client = client.clone();
+        let runtime = Handle::current();
+        move || plugin.run(&input, client, runtime, deadline)
+    });
+
+    match tokio::time::timeout(timeout, task).await {
+        Ok(Ok(Ok(outcome))) => outcome.apply(result),
+        Ok(Ok(Err(e))) => result.with_error(format!("{e:#}")),
+        Ok(Err(e)) => result.with_error(e.to_string()),
+        Err(_) => result.with_error(format!("timed out after {}s", plugin.limits.timeout_secs)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Outcome;
+
+    const MANIFEST: &str = r#"
+        name = "example"
+        domain = "example.com"
+        method = "register"
+        allowed_hosts = ["api.example.com"]
+    "#;
+
+    /// Plugin returning a fixed answer, with a bump allocator for host writes
+    fn answering(output: &str) -> String {
+        format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (global $next (mut i32) (i32.const 1024))
+                (data (i32.const 0) "{}")
+                (func (export "alloc") (param $len i32) (result i32)
+                    (local $ptr i32)
+                    (local.set $ptr (global.get $next))
+                    (global.set $next (i32.add (global.get $next) (local.get $len)))
+                    (local.get $ptr))
+                (func (export "check") (param i32 i32) (result i64)
+                    (i64.const {})))"#,
+            output.replace('"', "\\\""),
+            output.len()
+        )
+    }
+
+    fn write_plugin(dir: &Path, wat: &str) -> PathBuf {
+        let path = dir.join("example.wasm");
+        fs::write(&path, wat::parse_str(wat).unwrap()).unwrap();
+        fs::write(dir.join("example.toml"), MANIFEST).unwrap();
+        path
+    }
+
+    fn load(wat: &str, limits: &WasmConfig) -> Arc<WasmPlugin> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_plugin(dir.path(), wat);
+        Arc::new(WasmPlugin::load(&engine(), &path, limits).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_plugin_found() {
+        let plugin = load(&answering(r#"{"outcome":"found"}"#), &WasmConfig::default());
+
+        let result = check_plugin(&plugin, "644637111", "33", &reqwest::Client::new().into()).await;
+
+        assert_eq!(result.name, "example");
+        assert!(result.exists);
+        assert!(!result.rate_limit);
+    }
+
+    #[tokio::test]
+    async fn test_plugin_not_found() {
+        let plugin = load(&answering(r#"{"outcome":"not_found"}"#), &WasmConfig::default());
+
+        let result = check_plugin(&plugin, "644637111", "33", &reqwest::Client::new().into()).await;
+
+        assert!(!result.exists);
+        assert!(!result.rate_limit);
+    }
+
+    #[tokio::test]
+    async fn test_plugin_infinite_loop_runs_out_of_fuel() {
+        let wat = answering(r#"{"outcome":"found"}"#).replace("(i64.const", "(loop $spin (br $spin)) (i64.const");
+        let limits = WasmConfig {
+            fuel: 100_000,
+            ..WasmConfig::default()
+        };
+        let plugin = load(&wat, &limits);
+
+        let result = check_plugin(&plugin, "644637111", "33", &reqwest::Client::new().into()).await;
+
+        assert_eq!(result.outcome, Outcome::Error);
+        assert!(!result.exists);
+    }
+
+    #[tokio::test]
+    async fn test_plugin_trap_is_contained() {
+        let wat = answering(r#"{"outcome":"found"}"#).replace("(i64.const", "unreachable (i64.const");
+        let plugin = load(&wat, &WasmConfig::default());
+
+        let result = check_plugin(&plugin, "644637111", "33", &reqwest::Client::new().into()).await;
+
+        assert_eq!(result.outcome, Outcome::Error);
+    }
+
+    #[tokio::test]
+    async fn test_plugin_malformed_output() {
+        let plugin = load(&answering(r#"{"outcome":"maybe"}"#), &WasmConfig::default());
+
+        let result = check_plugin(&plugin, "644637111", "33", &reqwest::Client::new().into()).await;
+
+        assert_eq!(result.outcome, Outcome::Error);
+    }
+
+    #[test]
+    fn test_load_rejects_missing_exports() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = write_plugin(dir.path(), r#"(module (memory (export "memory") 1))"#);
+
+        assert!(WasmPlugin::load(&engine(), &path, &WasmConfig::default()).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_http_capability_restricted_to_allowed_hosts() {
+        let mut state = HostState {
+            limits: StoreLimits::default(),
+            client: reqwest::Client::new().into(),
+            runtime: Handle::current(),
+            allowed_hosts: vec!["api.example.com".to_owned()],
+            requests_left: 1,
+            max_response_bytes: 1024,
+            deadline: Instant::now() + Duration::from_secs(5),
+        };
+        let request = |url: &str| HttpRequest {
+            method: "GET".to_owned(),
+            url: url.to_owned(),
+            headers: BTreeMap::new(),
+            body: None,
+        };
+
+        assert!(state.authorize(&request("https://evil.example.net/")).is_err());
+        assert!(state.authorize(&request("file:///etc/passwd")).is_err());
+        assert!(state.authorize(&request("https://api.example.com/lookup")).is_ok());
+        assert!(state.authorize(&request("https://api.example.com/lookup")).is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_redirects_are_not_followed() {
+        let mut server = mockito::Server::new_async().await;
+        let port = server.socket_address().port();
+        let redirect = server
+            .mock("GET", "/lookup")
+            .with_status(302)
+            .with_header("location", &format!("http://localhost:{port}/exfiltrate"))
+            .create_async()
+            .await;
+        let escape = server.mock("GET", "/exfiltrate").expect(0).create_async().await;
+        let mut state = HostState {
+            limits: StoreLimits::default(),
+            client: sandboxed(reqwest::Client::builder()).build().unwrap().into(),
+            runtime: Handle::current(),
+            allowed_hosts: vec!["127.0.0.1".to_owned()],
+            requests_left: 2,
+            max_response_bytes: 1024,
+            deadline: Instant::now() + Duration::from_secs(5),
+        };
+        let request = |url: String| HttpRequest { method: "GET".to_owned(), url, headers: BTreeMap::new(), body: None };
+
+        let (response, state) = tokio::task::spawn_blocking(move || {
+            let response = state.fetch(&request(format!("http://127.0.0.1:{port}/lookup")));
+            let next = state.fetch(&request(format!("http://localhost:{port}/exfiltrate")));
+            assert!(next.error.unwrap().contains("allowed_hosts"));
+            (response, state.requests_left)
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(response.status, 302);
+        assert_eq!(response.headers["location"], format!("http://localhost:{port}/exfiltrate"));
+        assert_eq!(state, 1);
+        redirect.assert_async().await;
+        escape.assert_async().await;
+    }
+
+    #[test]
+    fn test_host_calls_trap_after_deadline() {
+        let wat = answering(r#"{"outcome":"found"}"#)
+            .replace("(memory", r#"(import "ignorant" "now_ms" (func $now (result i64))) (memory"#)
+            .replace("(i64.const", "(loop $spin (drop (call $now)) (br $spin)) (i64.const");
+        let limits = WasmConfig { fuel: u64::MAX, ..WasmConfig::default() };
+        let plugin = load(&wat, &limits);
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let started = Instant::now();
+
+        let result = plugin.run(b"{}", reqwest::Client::new().into(), runtime.handle().clone(), started + Duration::from_millis(200));
+
+        assert!(format!("{:#}", result.unwrap_err()).contains("deadline"));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_pack_roundtrip() {
+        assert_eq!(unpack(pack(1024, 19)), (1024, 19));
+        assert_eq!(unpack(pack(-1, 1)), (u32::MAX as usize, 1));
+    }
+}
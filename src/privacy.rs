@@ -0,0 +1,163 @@
+//! Pseudonymization and masking of phone numbers
+//!
+//! With `[privacy] pseudonymize = true`, nothing written to disk holds a phone
+//! number in clear. The history, results files and audit log store
+//! `hmac-sha256:<hex>`, an HMAC of the E.164 number keyed with
+//! `IGNORANT_PSEUDONYM_KEY`. The same number always gets the same pseudonym
+//! under the same key, so stored data can still be looked up by number when
+//! the key is provided.
+//!
+//! Masking only changes what is printed: `+33 644637111` becomes
+//! `+33 6•••••111`.
+
+use anyhow::{Result, bail};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::CheckResult;
+use crate::batch::Target;
+
+/// Environment variable holding the pseudonymization key
+pub const KEY_ENV: &str = "IGNORANT_PSEUDONYM_KEY";
+
+const PSEUDONYM_PREFIX: &str = "hmac-sha256:";
+
+/// Replaces numbers with keyed hashes
+#[derive(Clone)]
+pub struct Pseudonymizer {
+    key: Vec<u8>,
+}
+
+impl Pseudonymizer {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self { key: key.into() }
+    }
+
+    /// Pseudonymizer keyed with the value of an environment variable
+    pub fn from_env(var: &str) -> Result<Self> {
+        match std::env::var(var) {
+            Ok(key) if !key.is_empty() => Ok(Self::new(key)),
+            _ => bail!("{var} is not set; it holds the key numbers are hashed with"),
+        }
+    }
+
+    /// `hmac-sha256:<hex>` of the number in E.164 form
+    pub fn pseudonym(&self, target: &Target) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(target.e164().as_bytes());
+        format!("{PSEUDONYM_PREFIX}{}", hex::encode(mac.finalize().into_bytes()))
+    }
+}
+
+/// How a number is written to disk: its pseudonym, or the number itself
+pub fn stored(pseudonymizer: Option<&Pseudonymizer>, target: &Target) -> String {
+    match pseudonymizer {
+        Some(pseudonymizer) => pseudonymizer.pseudonym(target),
+        None => target.to_string(),
+    }
+}
+
+/// Forms of a number platforms see, longest first so the full number is not left half-redacted
+pub fn needles(target: &Target) -> [String; 3] {
+    [target.e164(), format!("{}{}", target.country_code, target.phone), target.phone.clone()]
+}
+
+/// Copy of a result safe to store: digits of the number are removed from its evidence
+pub fn redact(result: &CheckResult, target: &Target) -> CheckResult {
+    let mut result = result.clone();
+    if let Some(evidence) = &mut result.evidence {
+        for needle in needles(target) {
+            evidence.redact(&needle, "[redacted]");
+        }
+    }
+    result
+}
+
+/// Hide the middle digits of a stored or printed number (`+33 6•••••111`)
+///
+/// Anything other than `+<country code> <phone>`, such as a pseudonym, is
+/// returned unchanged.
+pub fn mask(number: &str) -> String {
+    match number.split_once(' ') {
+        Some((country_code, phone)) if country_code.starts_with('+') => format!("{country_code} {}", mask_phone(phone)),
+        _ => number.to_owned(),
+    }
+}
+
+/// Hide the middle digits of a phone number without its country code
+pub fn mask_phone(phone: &str) -> String {
+    let digits: Vec<char> = phone.chars().collect();
+    let (start, end) = match digits.len() {
+        0..=4 => (0, 0),
+        5..=6 => (1, 2),
+        _ => (1, 3),
+    };
+    let hidden = digits.len() - start - end;
+    digits[..start]
+        .iter()
+        .copied()
+        .chain(std::iter::repeat_n('•', hidden))
+        .chain(digits[digits.len() - end..].iter().copied())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(country_code: &str, phone: &str) -> Target {
+        Target { country_code: country_code.to_owned(), phone: phone.to_owned() }
+    }
+
+    #[test]
+    fn test_pseudonym_is_keyed_and_stable() {
+        let pseudonymizer = Pseudonymizer::new("secret");
+        let number = target("33", "644637111");
+
+        let pseudonym = pseudonymizer.pseudonym(&number);
+
+        assert!(pseudonym.starts_with("hmac-sha256:"));
+        assert!(!pseudonym.contains("644637111"));
+        assert_eq!(pseudonym, pseudonymizer.pseudonym(&number));
+        assert_ne!(pseudonym, Pseudonymizer::new("other").pseudonym(&number));
+        assert_ne!(pseudonym, pseudonymizer.pseudonym(&target("33", "644637112")));
+    }
+
+    #[test]
+    fn test_stored_number() {
+        let number = target("33", "644637111");
+
+        assert_eq!(stored(None, &number), "+33 644637111");
+        assert_eq!(stored(Some(&Pseudonymizer::new("secret")), &number), Pseudonymizer::new("secret").pseudonym(&number));
+    }
+
+    #[test]
+    fn test_mask() {
+        assert_eq!(mask("+33 644637111"), "+33 6•••••111");
+        assert_eq!(mask("+44 77009"), "+44 7••09");
+        assert_eq!(mask("+1 5550"), "+1 ••••");
+        assert_eq!(mask("hmac-sha256:abcd"), "hmac-sha256:abcd");
+    }
+
+    #[test]
+    fn test_redact_evidence() {
+        let mut result = CheckResult::new("example", "example.com", "register");
+        result.evidence = Some(crate::evidence::Evidence {
+            url: "https://example.com/check?phone=33644637111".to_owned(),
+            status: 200,
+            headers: Default::default(),
+            fields: crate::evidence::fields([("echo", "+33644637111".to_owned())]),
+            body_sha256: String::new(),
+            body_bytes: 0,
+            excerpt: "{\"q\": \"644637111\"}".to_owned(),
+            truncated: false,
+        });
+
+        let redacted = redact(&result, &target("33", "644637111"));
+
+        let evidence = redacted.evidence.unwrap();
+        assert_eq!(evidence.url, "https://example.com/check?phone=[redacted]");
+        assert_eq!(evidence.fields["echo"], "[redacted]");
+        assert_eq!(evidence.excerpt, "{\"q\": \"[redacted]\"}");
+    }
+}
@@ -0,0 +1,202 @@
+//! Per-domain request rate limiting
+//!
+//! Every request goes through a token bucket keyed by host, shared by all
+//! modules for the lifetime of the process. A `Retry-After` from a platform
+//! pauses requests to its host until the platform is ready again, whether or
+//! not the host is rate limited.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::RateLimitConfig;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    capacity: f64,
+    rate: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: f64, burst: u32, now: Instant) -> Self {
+        let capacity = f64::from(burst.max(1));
+        Self {
+            tokens: capacity,
+            capacity,
+            rate,
+            updated: now,
+        }
+    }
+
+    /// Take a token, or return how long to wait before one is available
+    fn take(&mut self, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+}
+
+/// Token-bucket limiter shared across all checks
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    /// Hosts that asked to be left alone, and until when
+    pauses: Mutex<HashMap<String, Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            buckets: Mutex::new(HashMap::new()),
+            pauses: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// A limiter that never delays requests
+    pub fn unlimited() -> Self {
+        Self::new(RateLimitConfig {
+            requests_per_second: 0.0,
+            ..RateLimitConfig::default()
+        })
+    }
+
+    fn with_bucket<T>(&self, host: &str, now: Instant, f: impl FnOnce(&mut Bucket) -> T) -> Option<T> {
+        let (rate, burst) = self.config.limits_for(host);
+        if rate <= 0.0 {
+            return None;
+        }
+        let mut buckets = self.buckets.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let bucket = buckets
+            .entry(host.to_ascii_lowercase())
+            .or_insert_with(|| Bucket::new(rate, burst, now));
+        Some(f(bucket))
+    }
+
+    /// How long requests to `host` are still paused for
+    fn paused_for(&self, host: &str, now: Instant) -> Option<Duration> {
+        let mut pauses = self.pauses.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let host = host.to_ascii_lowercase();
+        let until = *pauses.get(&host)?;
+        if now < until {
+            return Some(until - now);
+        }
+        pauses.remove(&host);
+        None
+    }
+
+    /// Wait until a request to `host` is allowed
+    pub async fn acquire(&self, host: &str) {
+        while let Some(wait) = self.paused_for(host, Instant::now()) {
+            tokio::time::sleep(wait).await;
+        }
+        loop {
+            match self.with_bucket(host, Instant::now(), |bucket| bucket.take(Instant::now())) {
+                None | Some(Ok(())) => return,
+                Some(Err(wait)) => tokio::time::sleep(wait).await,
+            }
+        }
+    }
+
+    /// Hold back requests to `host` for `duration`, capped at the configured maximum
+    pub fn pause(&self, host: &str, duration: Duration) {
+        let now = Instant::now();
+        let until = now + duration.min(Duration::from_secs(self.config.max_pause_secs));
+        let mut pauses = self.pauses.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let paused_until = pauses.entry(host.to_ascii_lowercase()).or_insert(until);
+        *paused_until = (*paused_until).max(until);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_allows_burst_then_waits() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(2.0, 2, now);
+
+        assert!(bucket.take(now).is_ok());
+        assert!(bucket.take(now).is_ok());
+        let wait = bucket.take(now).unwrap_err();
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+        assert!(bucket.take(now + Duration::from_millis(500)).is_ok());
+    }
+
+    #[test]
+    fn test_pause() {
+        let limiter = RateLimiter::new(RateLimitConfig::default());
+        let now = Instant::now();
+        limiter.pause("Accounts.Snapchat.com", Duration::from_secs(3));
+        limiter.pause("accounts.snapchat.com", Duration::from_secs(1));
+
+        assert!(limiter.paused_for("accounts.snapchat.com", now).unwrap() > Duration::from_secs(2));
+        assert!(limiter.paused_for("accounts.snapchat.com", now + Duration::from_secs(4)).is_none());
+        assert!(limiter.paused_for("i.instagram.com", now).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_retry_after_honoured_without_rate_limit() {
+        let limiter = RateLimiter::unlimited();
+        limiter.pause("accounts.snapchat.com", Duration::from_millis(300));
+        let start = Instant::now();
+
+        limiter.acquire("accounts.snapchat.com").await;
+
+        assert!(start.elapsed() >= Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_per_domain_overrides() {
+        let config: crate::config::Config = toml::from_str(
+            r#"
+            [rate_limit]
+            requests_per_second = 2.0
+            burst = 4
+            [rate_limit.domains."www.amazon.com"]
+            requests_per_second = 0.5
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.rate_limit.limits_for("www.amazon.com"), (0.5, 4));
+        assert_eq!(config.rate_limit.limits_for("i.instagram.com"), (2.0, 4));
+    }
+
+    #[tokio::test]
+    async fn test_unlimited_never_waits() {
+        let limiter = RateLimiter::unlimited();
+        let start = Instant::now();
+
+        for _ in 0..100 {
+            limiter.acquire("www.amazon.com").await;
+        }
+
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_pause_is_capped() {
+        let limiter = RateLimiter::new(RateLimitConfig {
+            max_pause_secs: 0,
+            ..RateLimitConfig::default()
+        });
+        limiter.pause("accounts.snapchat.com", Duration::from_secs(3600));
+        let start = Instant::now();
+
+        limiter.acquire("accounts.snapchat.com").await;
+
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
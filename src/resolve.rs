@@ -0,0 +1,122 @@
+//! Host resolution overrides
+//!
+//! `--resolve HOST:PORT:ADDR` and `[dns] resolve` work like curl's
+//! `--resolve`: connections to HOST go to ADDR instead of the address DNS
+//! returns, so platforms can be pointed at a local stand-in without editing
+//! `/etc/hosts`. ADDR may list several comma-separated addresses, IPv6 ones
+//! in brackets. Connections still use the port of the URL, so PORT names
+//! the port the stand-in listens on (443 for every built-in module).
+//!
+//! Requests sent through a proxy are resolved by the proxy, so overrides do
+//! not apply to them.
+
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+use anyhow::{Context, Result, anyhow, bail};
+use reqwest::ClientBuilder;
+
+/// One `HOST:PORT:ADDR` entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Override {
+    pub host: String,
+    pub port: u16,
+    pub addrs: Vec<IpAddr>,
+}
+
+impl FromStr for Override {
+    type Err = anyhow::Error;
+
+    fn from_str(entry: &str) -> Result<Self> {
+        let mut parts = entry.splitn(3, ':');
+        let (Some(host), Some(port), Some(addrs)) = (parts.next(), parts.next(), parts.next()) else {
+            bail!("`{entry}` is not HOST:PORT:ADDR");
+        };
+        if host.is_empty() {
+            bail!("`{entry}` has no host");
+        }
+        let port = port.parse().with_context(|| format!("`{entry}` has an invalid port"))?;
+        let addrs = addrs
+            .split(',')
+            .map(|addr| {
+                let addr = addr.trim();
+                addr.strip_prefix('[')
+                    .and_then(|addr| addr.strip_suffix(']'))
+                    .unwrap_or(addr)
+                    .parse::<IpAddr>()
+                    .map_err(|_| anyhow!("`{addr}` in `{entry}` is not an IP address"))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { host: host.to_ascii_lowercase(), port, addrs })
+    }
+}
+
+/// Parse overrides, refusing a host overridden twice
+pub fn parse(entries: &[String]) -> Result<Vec<Override>> {
+    let mut hosts = BTreeMap::new();
+    for entry in entries {
+        let entry: Override = entry.parse().context("invalid resolve override")?;
+        if let Some(previous) = hosts.insert(entry.host.clone(), entry) {
+            bail!("invalid resolve override: {} is overridden twice", previous.host);
+        }
+    }
+    Ok(hosts.into_values().collect())
+}
+
+/// Have a client connect to the overridden addresses
+pub fn apply(builder: ClientBuilder, overrides: &[Override]) -> ClientBuilder {
+    overrides.iter().fold(builder, |builder, entry| {
+        let addrs: Vec<SocketAddr> = entry.addrs.iter().map(|addr| SocketAddr::new(*addr, entry.port)).collect();
+        builder.resolve_to_addrs(&entry.host, &addrs)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_entries() {
+        let entry: Override = "WWW.Amazon.com:443:127.0.0.1".parse().unwrap();
+        assert_eq!(entry.host, "www.amazon.com");
+        assert_eq!(entry.port, 443);
+        assert_eq!(entry.addrs, ["127.0.0.1".parse::<IpAddr>().unwrap()]);
+
+        let entry: Override = "i.instagram.com:443:[::1],10.0.0.5".parse().unwrap();
+        assert_eq!(entry.addrs, ["::1".parse::<IpAddr>().unwrap(), "10.0.0.5".parse().unwrap()]);
+    }
+
+    #[test]
+    fn test_invalid_entries() {
+        for entry in [
+            "www.amazon.com",
+            "www.amazon.com:443",
+            ":443:127.0.0.1",
+            "www.amazon.com:https:127.0.0.1",
+            "www.amazon.com:443:localhost",
+        ] {
+            assert!(entry.parse::<Override>().is_err(), "{entry} should be rejected");
+        }
+        let err = parse(&["a.test:443:127.0.0.1".to_owned(), "A.test:80:127.0.0.2".to_owned()]).unwrap_err();
+        assert!(err.to_string().contains("a.test is overridden twice"));
+    }
+
+    #[tokio::test]
+    async fn test_client_connects_to_override() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/ap/signin")
+            .match_header("host", mockito::Matcher::Regex("^www.amazon.com".to_owned()))
+            .create_async()
+            .await;
+        let port = server.socket_address().port();
+        let overrides = parse(&[format!("www.amazon.com:{port}:127.0.0.1")]).unwrap();
+        let client = apply(reqwest::Client::builder().no_proxy(), &overrides).build().unwrap();
+
+        let response = client.get(format!("http://www.amazon.com:{port}/ap/signin")).send().await.unwrap();
+
+        assert_eq!(response.status(), 200);
+        mock.assert_async().await;
+    }
+}
@@ -0,0 +1,327 @@
+//! Retention of stored data
+//!
+//! `ignorant purge` removes data past the retention period set for its type
+//! in `[retention]`, or everything stored about one case or one number:
+//!
+//! - history: whole runs, or single results when purging a number
+//! - evidence: the `evidence` attached to stored results, which the results
+//!   themselves outlive
+//! - audit: audit log entries
+//!
+//! Files that lose records are rewritten with a fresh hash chain, so they
+//! still verify afterwards. The audit log also gets a record of the purge
+//! (see [`crate::audit`]). A history file that cannot be read or fails
+//! verification may hold what is to be purged, so the purge stops unless
+//! forced, in which case such files are left untouched and counted.
+//!
+//! A number is matched in every form it may have been stored in: in clear,
+//! and hashed with each key that is set, since pseudonymization may have
+//! been turned on or off since. Records hashed with a key that is not set
+//! cannot be matched.
+//!
+//! There is no retention period for a cache: responses are only kept in the
+//! history, as evidence and in the audit log. Files written on request
+//! (`--output`, `--record`, `--har`) are the user's and are not purged.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::audit::{self, Actor, Entry};
+use crate::config::RetentionConfig;
+use crate::custody::{self, ChainKey};
+use crate::history::History;
+
+/// What to purge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selector {
+    /// Data older than its retention period at this time
+    Expired(DateTime<Utc>),
+    /// Everything filed under a case
+    Case(String),
+    /// Everything about a number, in any of its stored forms (see [`crate::privacy::stored`])
+    Number(Vec<String>),
+}
+
+/// What a purge removed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    pub runs: usize,
+    pub results: usize,
+    pub evidence: usize,
+    pub audit_entries: usize,
+    /// History files left untouched because they could not be read
+    pub unreadable: usize,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = |n: usize, one: &str, many: &str| format!("{n} {}", if n == 1 { one } else { many });
+        write!(
+            f,
+            "{}, {}, evidence of {} and {}",
+            count(self.runs, "run", "runs"),
+            count(self.results, "result", "results"),
+            count(self.evidence, "result", "results"),
+            count(self.audit_entries, "audit entry", "audit entries"),
+        )?;
+        if self.unreadable > 0 {
+            write!(f, "; {} left untouched", count(self.unreadable, "unreadable file", "unreadable files"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Start of the retention window for a period in days; `None` keeps data forever
+fn cutoff(now: DateTime<Utc>, days: Option<u64>) -> Option<DateTime<Utc>> {
+    let days = i64::try_from(days?).ok()?;
+    Some(now - TimeDelta::try_days(days)?)
+}
+
+/// Purge runs, results and evidence from the history
+///
+/// Fails before changing anything when a file cannot be read, unless `force` is set.
+pub fn purge_history(
+    history: &History,
+    retention: &RetentionConfig,
+    selector: &Selector,
+    dry_run: bool,
+    force: bool,
+) -> Result<Report> {
+    let (runs, unreadable) = history.scan()?;
+    if !unreadable.is_empty() && !force {
+        let files: Vec<String> = unreadable.iter().map(|(path, e)| format!("{}: {e:#}", path.display())).collect();
+        bail!(
+            "{} history files could not be read, and may hold data to purge; \
+             fix or remove them, or pass --force to leave them untouched:\n  {}",
+            unreadable.len(),
+            files.join("\n  ")
+        );
+    }
+    let mut report = Report { unreadable: unreadable.len(), ..Report::default() };
+    for (path, e) in &unreadable {
+        eprintln!("Skipping {}: {e:#}", path.display());
+    }
+    for (path, mut run) in runs {
+        let drop_run = match selector {
+            Selector::Expired(now) => cutoff(*now, retention.history_days).is_some_and(|cutoff| run.metadata.finished_at < cutoff),
+            Selector::Case(case_id) => run.metadata.case_id.as_ref() == Some(case_id),
+            Selector::Number(_) => false,
+        };
+        if drop_run {
+            report.runs += 1;
+            report.results += run.results.len();
+            if !dry_run {
+                fs::remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))?;
+            }
+            continue;
+        }
+
+        let before = run.results.len();
+        let mut stripped = 0;
+        match selector {
+            Selector::Expired(now) => {
+                if let Some(cutoff) = cutoff(*now, retention.evidence_days) {
+                    for (_, result) in &mut run.results {
+                        let checked_at = result.checked_at.unwrap_or(run.metadata.finished_at);
+                        if checked_at < cutoff && result.evidence.take().is_some() {
+                            stripped += 1;
+                        }
+                    }
+                }
+            }
+            Selector::Number(forms) => {
+                run.results.retain(|(stored, _)| !forms.contains(stored));
+                run.metadata.authorizations.retain(|stored, _| !forms.contains(stored));
+            }
+            Selector::Case(_) => {}
+        }
+        let removed = before - run.results.len();
+        if removed == 0 && stripped == 0 {
+            continue;
+        }
+        report.results += removed;
+        report.evidence += stripped;
+        if run.results.is_empty() {
+            report.runs += 1;
+        }
+        if dry_run {
+            continue;
+        }
+        if run.results.is_empty() {
+            fs::remove_file(&path).with_context(|| format!("failed to remove {}", path.display()))?;
+        } else {
+            custody::write(&path, &run.metadata, &run.results, history.key(), history.vault())?;
+        }
+    }
+    Ok(report)
+}
+
+impl Selector {
+    /// What is purged, as recorded in the audit log
+    ///
+    /// A purged number is not named, or the record would keep it.
+    fn describe(&self) -> String {
+        match self {
+            Self::Expired(_) => "expired".to_owned(),
+            Self::Case(case_id) => format!("case {case_id}"),
+            Self::Number(_) => "number".to_owned(),
+        }
+    }
+}
+
+/// Purge audit log entries, returning how many were removed
+pub fn purge_audit(
+    path: &Path,
+    key: &ChainKey,
+    actor: &Actor,
+    retention: &RetentionConfig,
+    selector: &Selector,
+    dry_run: bool,
+) -> Result<usize> {
+    if !path.exists() {
+        return Ok(0);
+    }
+    let expired = |entry: &Entry| match selector {
+        Selector::Expired(now) => cutoff(*now, retention.audit_days).is_some_and(|cutoff| entry.timestamp < cutoff),
+        Selector::Case(case_id) => entry.case_id.as_ref() == Some(case_id),
+        Selector::Number(forms) => forms.contains(&entry.number),
+    };
+    if dry_run {
+        return Ok(audit::read(path, key)?.iter().filter(|entry| expired(entry)).count());
+    }
+    audit::retain(path, key, actor, &selector.describe(), |entry| !expired(entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::custody::RunMetadata;
+    use crate::evidence::Evidence;
+    use crate::{CheckResult, Outcome};
+
+    fn save(history: &History, case_id: &str, days_ago: i64, numbers: &[&str]) {
+        let at = Utc::now() - TimeDelta::days(days_ago);
+        let metadata = RunMetadata {
+            run_id: uuid::Uuid::new_v4().to_string(),
+            tool_version: "1.2.0".to_owned(),
+            modules: BTreeMap::new(),
+            host: "workstation".to_owned(),
+            operator: None,
+            case_id: Some(case_id.to_owned()),
+            authorizations: numbers.iter().map(|number| ((*number).to_owned(), "REF".to_owned())).collect(),
+            note: None,
+            started_at: at,
+            finished_at: at,
+        };
+        let results: Vec<(String, CheckResult)> = numbers
+            .iter()
+            .map(|number| {
+                let mut result = CheckResult::new("example", "example.com", "register").with_exists(true);
+                result.checked_at = Some(at);
+                result.evidence = Some(Evidence {
+                    url: "https://example.com/".to_owned(),
+                    status: 200,
+                    headers: BTreeMap::new(),
+                    fields: BTreeMap::new(),
+                    body_sha256: String::new(),
+                    body_bytes: 0,
+                    excerpt: String::new(),
+                    truncated: false,
+                });
+                ((*number).to_owned(), result)
+            })
+            .collect();
+        history.save(&metadata, &results).unwrap();
+    }
+
+    fn pseudonym(phone: &str) -> String {
+        let target = crate::batch::Target { country_code: "33".to_owned(), phone: phone.to_owned() };
+        crate::privacy::Pseudonymizer::new("secret").pseudonym(&target)
+    }
+
+    fn retention() -> RetentionConfig {
+        RetentionConfig { history_days: Some(90), evidence_days: Some(30), audit_days: Some(365) }
+    }
+
+    #[test]
+    fn test_purge_expired_history_and_evidence() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(dir.path().to_owned(), ChainKey::new("custody secret"));
+        save(&history, "old", 100, &["+33 644637111"]);
+        save(&history, "recent", 40, &["+33 644637111", "+33 644637112"]);
+        save(&history, "new", 1, &["+33 644637111"]);
+
+        let selector = Selector::Expired(Utc::now());
+        let planned = purge_history(&history, &retention(), &selector, true, false).unwrap();
+        let report = purge_history(&history, &retention(), &selector, false, false).unwrap();
+
+        assert_eq!(planned, report);
+        assert_eq!(report, Report { runs: 1, results: 1, evidence: 2, ..Report::default() });
+        let runs = history.runs().unwrap();
+        assert_eq!(runs.len(), 2);
+        assert!(runs[0].results.iter().all(|(_, result)| result.evidence.is_none()));
+        assert!(runs[1].results[0].1.evidence.is_some());
+        assert_eq!(runs[0].results[0].1.outcome, Outcome::Found);
+    }
+
+    #[test]
+    fn test_purge_number_and_case() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(dir.path().to_owned(), ChainKey::new("custody secret"));
+        let forms = vec!["+33 644637111".to_owned(), pseudonym("644637111")];
+        save(&history, "2024-0042", 1, &["+33 644637111", "+33 644637112"]);
+        save(&history, "2024-0043", 1, &["+33 644637111"]);
+        // Saved while pseudonymization was on
+        save(&history, "2024-0044", 1, &[&pseudonym("644637111")]);
+
+        let report = purge_history(&history, &retention(), &Selector::Number(forms), false, false).unwrap();
+
+        assert_eq!(report, Report { runs: 2, results: 3, ..Report::default() });
+        let runs = history.runs().unwrap();
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].results.len(), 1);
+        assert!(!runs[0].metadata.authorizations.contains_key("+33 644637111"));
+
+        let report = purge_history(&history, &retention(), &Selector::Case("2024-0042".to_owned()), false, false).unwrap();
+        assert_eq!(report.runs, 1);
+        assert!(history.runs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_unreadable_file_stops_purge_unless_forced() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(dir.path().to_owned(), ChainKey::new("custody secret"));
+        save(&history, "2024-0042", 1, &["+33 644637111"]);
+        save(&history, "2024-0043", 1, &["+33 644637111"]);
+        let tampered = history.files().unwrap()[0].0.clone();
+        fs::write(&tampered, "not a results file\n").unwrap();
+        let selector = Selector::Number(vec!["+33 644637111".to_owned()]);
+
+        let err = purge_history(&history, &retention(), &selector, false, false).unwrap_err();
+        assert!(err.to_string().contains("--force"), "{err}");
+        assert_eq!(history.runs().unwrap().len(), 1);
+
+        let report = purge_history(&history, &retention(), &selector, false, true).unwrap();
+        assert_eq!(report, Report { runs: 1, results: 1, unreadable: 1, ..Report::default() });
+        assert!(report.to_string().ends_with("; 1 unreadable file left untouched"));
+        assert!(tampered.exists());
+    }
+
+    #[test]
+    fn test_unlimited_retention_keeps_everything() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::new(dir.path().to_owned(), ChainKey::new("custody secret"));
+        save(&history, "old", 1000, &["+33 644637111"]);
+
+        let report = purge_history(&history, &RetentionConfig::default(), &Selector::Expired(Utc::now()), false, false).unwrap();
+
+        assert_eq!(report, Report::default());
+        assert_eq!(history.runs().unwrap().len(), 1);
+    }
+}
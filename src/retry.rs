@@ -0,0 +1,158 @@
+//! Retrying transient failures
+//!
+//! Only checks whose outcome is transient (see [`Outcome::is_transient`]) are
+//! retried. A definitive answer, including a rate limit, is returned as is.
+
+use std::future::Future;
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::CheckResult;
+use crate::config::{self, RetryConfig};
+
+/// Retry policy for a single module
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    /// Resolve the policy for `module`, applying its overrides on top of the defaults
+    pub fn for_module(config: &RetryConfig, module: &str) -> Self {
+        let overrides = config::module_override(&config.modules, module).cloned().unwrap_or_default();
+        Self {
+            max_attempts: overrides.max_attempts.unwrap_or(config.max_attempts).max(1),
+            base_delay: Duration::from_millis(overrides.base_delay_ms.unwrap_or(config.base_delay_ms)),
+            max_delay: Duration::from_millis(overrides.max_delay_ms.unwrap_or(config.max_delay_ms)),
+            jitter: overrides.jitter.unwrap_or(config.jitter).clamp(0.0, 1.0),
+        }
+    }
+
+    /// Delay before retrying after `attempt` failed attempts, without jitter
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+
+    /// Delay before retrying after `attempt` failed attempts
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self.backoff(attempt);
+        let spread = rand::thread_rng().gen_range(0.0..=self.jitter);
+        backoff.mul_f64(1.0 - spread)
+    }
+}
+
+/// Run `check` until it produces a non-transient outcome or the attempts run out
+///
+/// The number of attempts made is recorded in the returned result.
+pub async fn with_retry<F, Fut>(policy: RetryPolicy, mut check: F) -> CheckResult
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = CheckResult>,
+{
+    let mut attempt = 1;
+    loop {
+        let mut result = check().await;
+        result.attempts = attempt;
+        if !result.outcome.is_transient() || attempt >= policy.max_attempts {
+            return result;
+        }
+        tokio::time::sleep(policy.delay(attempt)).await;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Outcome;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(4),
+            jitter: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_backoff_doubles_and_caps() {
+        let policy = policy(5);
+
+        assert_eq!(policy.backoff(1), Duration::from_millis(1));
+        assert_eq!(policy.backoff(2), Duration::from_millis(2));
+        assert_eq!(policy.backoff(3), Duration::from_millis(4));
+        assert_eq!(policy.backoff(10), Duration::from_millis(4));
+        assert!(policy.delay(3) <= Duration::from_millis(4));
+    }
+
+    #[test]
+    fn test_policy_overrides() {
+        let config: crate::config::Config = toml::from_str(
+            r#"
+            [retry]
+            max_attempts = 2
+            [retry.modules.amazon]
+            max_attempts = 4
+            jitter = 0.0
+            "#,
+        )
+        .unwrap();
+
+        let amazon = RetryPolicy::for_module(&config.retry, "amazon");
+        let snapchat = RetryPolicy::for_module(&config.retry, "snapchat");
+
+        assert_eq!(amazon.max_attempts, 4);
+        assert_eq!(amazon.jitter, 0.0);
+        assert_eq!(snapchat.max_attempts, 2);
+        assert_eq!(snapchat.jitter, 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_retries_transient_errors() {
+        let calls = AtomicU32::new(0);
+
+        let result = with_retry(policy(3), || async {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            let result = CheckResult::new("amazon", "amazon.com", "login");
+            if call < 2 { result.with_error("connection reset") } else { result.with_exists(true) }
+        })
+        .await;
+
+        assert!(result.exists);
+        assert_eq!(result.attempts, 3);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let result = with_retry(policy(2), || async {
+            CheckResult::new("amazon", "amazon.com", "login").with_error("connection reset")
+        })
+        .await;
+
+        assert_eq!(result.outcome, Outcome::Error);
+        assert_eq!(result.attempts, 2);
+    }
+
+    #[tokio::test]
+    async fn test_definitive_answers_not_retried() {
+        let calls = AtomicU32::new(0);
+
+        let result = with_retry(policy(3), || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            CheckResult::new("snapchat", "snapchat.com", "register").with_rate_limit()
+        })
+        .await;
+
+        assert!(result.rate_limit);
+        assert_eq!(result.attempts, 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,165 @@
+//! Scope allowlist
+//!
+//! With scope enforcement on, every number must match an entry of the
+//! allowlist before any request is sent. Each line holds a pattern and the
+//! authorization reference that covers it:
+//!
+//! ```text
+//! # pattern                  authorization
+//! +33644637111               WARRANT-2024-0042
+//! +4477009*                  ENGAGEMENT-17
+//! +15550100000-+15550199999  ORG-RANGE-3
+//! ```
+//!
+//! Numbers are written in full (country code and phone, `+` optional). A
+//! trailing `*` makes a prefix; `A-B` is an inclusive range of numbers with
+//! the same number of digits. Blank lines and lines starting with `#` are
+//! ignored.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+
+use crate::batch::Target;
+
+/// Numbers an entry covers
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Pattern {
+    Exact(String),
+    Prefix(String),
+    Range(String, String),
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Result<Self> {
+        let pattern = if let Some(prefix) = pattern.strip_suffix('*') {
+            Self::Prefix(digits(prefix)?)
+        } else if let Some((low, high)) = pattern.split_once('-') {
+            let (low, high) = (digits(low)?, digits(high)?);
+            if low.len() != high.len() {
+                bail!("range bounds `{low}` and `{high}` have different lengths");
+            }
+            if low > high {
+                bail!("range `{low}-{high}` is empty");
+            }
+            Self::Range(low, high)
+        } else {
+            Self::Exact(digits(pattern)?)
+        };
+        Ok(pattern)
+    }
+
+    fn matches(&self, number: &str) -> bool {
+        match self {
+            Self::Exact(exact) => number == exact,
+            Self::Prefix(prefix) => number.starts_with(prefix.as_str()),
+            // Same length, so digit strings compare like the numbers they spell
+            Self::Range(low, high) => number.len() == low.len() && low.as_str() <= number && number <= high.as_str(),
+        }
+    }
+}
+
+/// A number in full, without its leading `+`
+fn digits(number: &str) -> Result<String> {
+    let number = number.strip_prefix('+').unwrap_or(number);
+    if number.is_empty() || !number.chars().all(|c| c.is_ascii_digit()) {
+        bail!("`{number}` is not a number");
+    }
+    Ok(number.to_owned())
+}
+
+/// Parsed allowlist
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scope {
+    entries: Vec<(Pattern, String)>,
+}
+
+impl Scope {
+    /// Parse the contents of an allowlist file
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut entries = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (pattern, authorization) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let authorization = authorization.trim();
+            if authorization.is_empty() {
+                bail!("line {}: `{pattern}` has no authorization reference", number + 1);
+            }
+            let pattern = Pattern::parse(pattern).with_context(|| format!("line {}", number + 1))?;
+            entries.push((pattern, authorization.to_owned()));
+        }
+        Ok(Self { entries })
+    }
+
+    /// Read and parse an allowlist file
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("failed to read scope allowlist {}", path.display()))?;
+        Self::parse(&contents).with_context(|| format!("invalid scope allowlist {}", path.display()))
+    }
+
+    /// Authorization reference covering a number, if it is in scope
+    pub fn authorize(&self, target: &Target) -> Option<&str> {
+        let number = format!("{}{}", target.country_code.trim_start_matches('+'), target.phone);
+        self.entries
+            .iter()
+            .find(|(pattern, _)| pattern.matches(&number))
+            .map(|(_, authorization)| authorization.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(country_code: &str, phone: &str) -> Target {
+        Target { country_code: country_code.to_owned(), phone: phone.to_owned() }
+    }
+
+    const ALLOWLIST: &str = "
+        # pattern authorization
+        +33644637111  WARRANT-2024-0042
+        4477009*      ENGAGEMENT 17
+        +15550100000-+15550199999 ORG-RANGE-3
+    ";
+
+    #[test]
+    fn test_authorize_patterns() {
+        let scope = Scope::parse(ALLOWLIST).unwrap();
+
+        assert_eq!(scope.authorize(&target("33", "644637111")), Some("WARRANT-2024-0042"));
+        assert_eq!(scope.authorize(&target("+44", "7700900123")), Some("ENGAGEMENT 17"));
+        assert_eq!(scope.authorize(&target("1", "5550150000")), Some("ORG-RANGE-3"));
+    }
+
+    #[test]
+    fn test_out_of_scope() {
+        let scope = Scope::parse(ALLOWLIST).unwrap();
+
+        assert_eq!(scope.authorize(&target("33", "644637112")), None);
+        assert_eq!(scope.authorize(&target("44", "7700800000")), None);
+        assert_eq!(scope.authorize(&target("1", "5550200000")), None);
+        // Longer than the range bounds
+        assert_eq!(scope.authorize(&target("1", "55501500001")), None);
+        assert_eq!(Scope::parse("").unwrap().authorize(&target("33", "644637111")), None);
+    }
+
+    #[test]
+    fn test_authorization_reference_required() {
+        let err = Scope::parse("+33644637111\n").unwrap_err();
+
+        assert!(err.to_string().contains("line 1: `+33644637111` has no authorization reference"));
+    }
+
+    #[test]
+    fn test_invalid_patterns() {
+        assert!(Scope::parse("+33-644 WARRANT").is_err());
+        assert!(Scope::parse("* EVERYTHING").is_err());
+        assert!(Scope::parse("15550100000-1555019999 REF").is_err());
+        assert!(Scope::parse("15550199999-15550100000 REF").is_err());
+    }
+}
@@ -0,0 +1,266 @@
+//! Signed site-definition bundles
+//!
+//! A bundle is a directory or `.tar.gz` archive holding:
+//!
+//! - `manifest.json`: bundle name, version and the SHA-256 of every site file
+//! - `manifest.sig`: hex-encoded Ed25519 signature over the exact manifest bytes
+//! - the site definition files listed in the manifest
+//!
+//! A bundle only loads if its signature verifies against one of the trusted
+//! keys from the configuration and every listed file matches its digest.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow, bail};
+use ed25519_dalek::{Signature, VerifyingKey};
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use super::{Site, SiteDefinition};
+
+pub const MANIFEST_FILE: &str = "manifest.json";
+pub const SIGNATURE_FILE: &str = "manifest.sig";
+
+/// Contents of `manifest.json`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    /// Bundle name (e.g. "team-sites")
+    pub name: String,
+    /// Bundle version, recorded in every result produced by its sites
+    pub version: String,
+    /// Site definition files shipped in the bundle
+    pub sites: Vec<ManifestEntry>,
+}
+
+/// A file listed in the manifest
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestEntry {
+    /// Path relative to the bundle root
+    pub path: String,
+    /// Hex-encoded SHA-256 of the file contents
+    pub sha256: String,
+}
+
+/// A verified bundle
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    pub manifest: Manifest,
+    /// Trusted key that produced the signature
+    pub signer: VerifyingKey,
+    pub sites: Vec<Site>,
+}
+
+/// Where bundle files are read from
+enum Source {
+    Directory(PathBuf),
+    Archive(HashMap<String, Vec<u8>>),
+}
+
+impl Source {
+    fn open(path: &Path) -> Result<Self> {
+        if path.is_dir() {
+            return Ok(Self::Directory(path.to_owned()));
+        }
+
+        let file = File::open(path).with_context(|| format!("failed to open bundle {}", path.display()))?;
+        let mut archive = tar::Archive::new(GzDecoder::new(file));
+        let mut files = HashMap::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+            let name = entry.path()?.to_string_lossy().trim_start_matches("./").to_owned();
+            let mut contents = Vec::new();
+            entry.read_to_end(&mut contents)?;
+            files.insert(name, contents);
+        }
+        Ok(Self::Archive(files))
+    }
+
+    fn read(&self, name: &str) -> Result<Vec<u8>> {
+        if !Path::new(name).components().all(|c| matches!(c, Component::Normal(_))) {
+            bail!("bundle path {name:?} must be relative and stay inside the bundle");
+        }
+        match self {
+            Self::Directory(root) => {
+                fs::read(root.join(name)).with_context(|| format!("bundle is missing {name}"))
+            }
+            Self::Archive(files) => files
+                .get(name)
+                .cloned()
+                .ok_or_else(|| anyhow!("bundle is missing {name}")),
+        }
+    }
+}
+
+/// Parse the hex-encoded public keys from the configuration
+pub fn parse_trusted_keys(keys: &[String]) -> Result<Vec<VerifyingKey>> {
+    keys.iter()
+        .map(|key| {
+            let bytes: [u8; 32] = hex::decode(key.trim())
+                .ok()
+                .and_then(|bytes| bytes.try_into().ok())
+                .ok_or_else(|| anyhow!("trusted key {key:?} is not 32 hex-encoded bytes"))?;
+            VerifyingKey::from_bytes(&bytes).with_context(|| format!("trusted key {key:?} is invalid"))
+        })
+        .collect()
+}
+
+fn verify_signature(manifest: &[u8], signature: &[u8], trusted_keys: &[VerifyingKey]) -> Result<VerifyingKey> {
+    if trusted_keys.is_empty() {
+        bail!("no trusted keys configured; add them to `trusted_keys` in the [sites] config section");
+    }
+
+    let signature = std::str::from_utf8(signature)
+        .ok()
+        .and_then(|sig| hex::decode(sig.trim()).ok())
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or_else(|| anyhow!("{SIGNATURE_FILE} is not a hex-encoded Ed25519 signature"))?;
+
+    trusted_keys
+        .iter()
+        .find(|key| key.verify_strict(manifest, &signature).is_ok())
+        .copied()
+        .ok_or_else(|| anyhow!("signature does not match any trusted key"))
+}
+
+/// Load a bundle, verifying its signature and file digests
+pub fn load(path: &Path, trusted_keys: &[VerifyingKey]) -> Result<Bundle> {
+    let source = Source::open(path)?;
+    let manifest_bytes = source.read(MANIFEST_FILE)?;
+    let signature = source.read(SIGNATURE_FILE)?;
+
+    let signer = verify_signature(&manifest_bytes, &signature, trusted_keys)
+        .with_context(|| format!("bundle {} failed verification", path.display()))?;
+
+    let manifest: Manifest = serde_json::from_slice(&manifest_bytes).context("invalid bundle manifest")?;
+
+    let mut sites = Vec::with_capacity(manifest.sites.len());
+    for entry in &manifest.sites {
+        let contents = source.read(&entry.path)?;
+        let digest = hex::encode(Sha256::digest(&contents));
+        if !digest.eq_ignore_ascii_case(entry.sha256.trim()) {
+            bail!("{} does not match the digest in the manifest", entry.path);
+        }
+
+        let definition: SiteDefinition = serde_json::from_slice(&contents)
+            .with_context(|| format!("invalid site definition {}", entry.path))?;
+        definition.validate()?;
+        sites.push(Site {
+            definition,
+            bundle_version: manifest.version.clone(),
+        });
+    }
+
+    Ok(Bundle { manifest, signer, sites })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const SITE: &str = r#"{
+        "name": "example",
+        "domain": "example.com",
+        "method": "register",
+        "request": {"url": "https://example.com/check/{phone}"},
+        "found": {"status": 200},
+        "not_found": {"status": 404}
+    }"#;
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_bytes(&[7; 32])
+    }
+
+    fn manifest(site: &str) -> String {
+        format!(
+            r#"{{"name": "test-sites", "version": "2024.1", "sites": [{{"path": "sites/example.json", "sha256": "{}"}}]}}"#,
+            hex::encode(Sha256::digest(site.as_bytes()))
+        )
+    }
+
+    fn write_bundle(dir: &Path, key: &SigningKey) {
+        let manifest = manifest(SITE);
+        fs::create_dir_all(dir.join("sites")).unwrap();
+        fs::write(dir.join("sites/example.json"), SITE).unwrap();
+        fs::write(dir.join(MANIFEST_FILE), &manifest).unwrap();
+        fs::write(dir.join(SIGNATURE_FILE), hex::encode(key.sign(manifest.as_bytes()).to_bytes())).unwrap();
+    }
+
+    #[test]
+    fn test_load_directory_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        write_bundle(dir.path(), &signing_key());
+
+        let bundle = load(dir.path(), &[signing_key().verifying_key()]).unwrap();
+
+        assert_eq!(bundle.manifest.name, "test-sites");
+        assert_eq!(bundle.signer, signing_key().verifying_key());
+        assert_eq!(bundle.sites.len(), 1);
+        assert_eq!(bundle.sites[0].bundle_version, "2024.1");
+    }
+
+    #[test]
+    fn test_load_archive_bundle() {
+        let dir = tempfile::tempdir().unwrap();
+        let contents = dir.path().join("contents");
+        write_bundle(&contents, &signing_key());
+
+        let archive_path = dir.path().join("bundle.tar.gz");
+        let encoder = flate2::write::GzEncoder::new(File::create(&archive_path).unwrap(), flate2::Compression::default());
+        let mut builder = tar::Builder::new(encoder);
+        builder.append_dir_all(".", &contents).unwrap();
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let bundle = load(&archive_path, &[signing_key().verifying_key()]).unwrap();
+
+        assert_eq!(bundle.sites[0].definition.name, "example");
+    }
+
+    #[test]
+    fn test_untrusted_key_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        write_bundle(dir.path(), &signing_key());
+        let other = SigningKey::from_bytes(&[8; 32]).verifying_key();
+
+        assert!(load(dir.path(), &[other]).is_err());
+        assert!(load(dir.path(), &[]).is_err());
+    }
+
+    #[test]
+    fn test_tampered_site_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        write_bundle(dir.path(), &signing_key());
+        fs::write(dir.path().join("sites/example.json"), SITE.replace("example.com", "evil.com")).unwrap();
+
+        let err = load(dir.path(), &[signing_key().verifying_key()]).unwrap_err();
+
+        assert!(err.to_string().contains("digest"));
+    }
+
+    #[test]
+    fn test_tampered_manifest_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        write_bundle(dir.path(), &signing_key());
+        let manifest = fs::read_to_string(dir.path().join(MANIFEST_FILE)).unwrap();
+        fs::write(dir.path().join(MANIFEST_FILE), manifest.replace("2024.1", "2024.2")).unwrap();
+
+        assert!(load(dir.path(), &[signing_key().verifying_key()]).is_err());
+    }
+
+    #[test]
+    fn test_parse_trusted_keys() {
+        let key = hex::encode(signing_key().verifying_key().to_bytes());
+
+        assert_eq!(parse_trusted_keys(&[key]).unwrap(), vec![signing_key().verifying_key()]);
+        assert!(parse_trusted_keys(&["abcd".to_owned()]).is_err());
+    }
+}
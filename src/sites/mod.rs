@@ -0,0 +1,339 @@
+//! Declarative site definitions
+//!
+//! A site definition describes a single request to send with the target
+//! number and how to read the response, so simple checks can be added
+//! without writing a module. Definitions are only ever loaded from signed
+//! bundles (see [`bundle`]).
+
+use std::collections::BTreeMap;
+
+use anyhow::{Result, bail};
+use rand::Rng;
+use reqwest::Method;
+use serde::Deserialize;
+
+use crate::{CheckResult, challenge, evidence, http::HttpClient, user_agents::USER_AGENTS};
+
+/// Signed bundle loading and verification
+pub mod bundle;
+
+/// A single platform check described as data
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SiteDefinition {
+    /// Platform name (e.g. "example")
+    pub name: String,
+    /// Platform domain (e.g. "example.com")
+    pub domain: String,
+    /// Method used for checking (e.g. "login", "register", "other")
+    pub method: String,
+    /// Whether this platform frequently rate limits requests
+    #[serde(default)]
+    pub frequent_rate_limit: bool,
+    /// Request to send
+    pub request: RequestTemplate,
+    /// Response shape meaning the number is registered
+    pub found: Matcher,
+    /// Response shape meaning the number is not registered
+    pub not_found: Matcher,
+}
+
+/// HTTP request with `{country_code}` and `{phone}` placeholders
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RequestTemplate {
+    /// HTTP method (default: GET)
+    #[serde(default = "default_method")]
+    pub method: String,
+    /// Request URL
+    pub url: String,
+    /// Extra request headers
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Request body
+    #[serde(default)]
+    pub body: Option<String>,
+}
+
+fn default_method() -> String {
+    "GET".to_owned()
+}
+
+/// Conditions on a response; every condition that is set must hold
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Matcher {
+    /// Expected HTTP status code
+    #[serde(default)]
+    pub status: Option<u16>,
+    /// Substring the response body must contain
+    #[serde(default)]
+    pub body_contains: Option<String>,
+}
+
+impl Matcher {
+    fn is_empty(&self) -> bool {
+        self.status.is_none() && self.body_contains.is_none()
+    }
+
+    fn matches(&self, status: u16, body: &str) -> bool {
+        self.status.is_none_or(|expected| expected == status)
+            && self.body_contains.as_deref().is_none_or(|needle| body.contains(needle))
+    }
+}
+
+impl SiteDefinition {
+    /// Reject definitions that could never produce a meaningful answer
+    pub fn validate(&self) -> Result<()> {
+        if self.name.is_empty() || self.domain.is_empty() {
+            bail!("site definition must have a name and a domain");
+        }
+        if Method::from_bytes(self.request.method.as_bytes()).is_err() {
+            bail!("site {}: invalid HTTP method {:?}", self.name, self.request.method);
+        }
+        if self.found.is_empty() || self.not_found.is_empty() {
+            bail!("site {}: `found` and `not_found` need at least one condition", self.name);
+        }
+        Ok(())
+    }
+}
+
+/// A site definition together with the bundle it was loaded from
+#[derive(Debug, Clone)]
+pub struct Site {
+    pub definition: SiteDefinition,
+    /// Version of the bundle this definition came from
+    pub bundle_version: String,
+}
+
+impl Site {
+    /// Empty result for this site, before any request is made
+    pub fn result(&self) -> CheckResult {
+        let definition = &self.definition;
+        let mut result = CheckResult::new(&definition.name, &definition.domain, &definition.method)
+            .with_bundle_version(&self.bundle_version);
+        result.frequent_rate_limit = definition.frequent_rate_limit;
+        result
+    }
+}
+
+fn render(template: &str, phone: &str, country_code: &str) -> String {
+    template
+        .replace("{country_code}", country_code)
+        .replace("{phone}", phone)
+}
+
+/// Check a phone number against a declarative site definition
+///
+/// The response is compared against `found` first, then `not_found`. A
+/// response matching neither is reported as blocked when it is a challenge
+/// page, and as unexpected otherwise, since the platform answered in a way
+/// the definition does not describe; a request that fails outright is an error.
+pub async fn check_site(site: &Site, phone: &str, country_code: &str, client: &HttpClient) -> CheckResult {
+    let definition = &site.definition;
+    let result = site.result();
+
+    let Ok(method) = Method::from_bytes(definition.request.method.as_bytes()) else {
+        return result.with_rate_limit();
+    };
+
+    let url = render(&definition.request.url, phone, country_code);
+    let mut request = client.request(method, url);
+    if !definition.request.headers.keys().any(|key| key.eq_ignore_ascii_case("user-agent")) {
+        let user_agent = USER_AGENTS.chrome[rand::thread_rng().gen_range(0..USER_AGENTS.chrome.len())];
+        request = request.header("User-Agent", user_agent);
+    }
+    for (key, value) in &definition.request.headers {
+        request = request.header(key, render(value, phone, country_code));
+    }
+    if let Some(body) = &definition.request.body {
+        request = request.body(render(body, phone, country_code));
+    }
+
+    match client.send(request).await {
+        Ok(response) => match client.read(response).await {
+            Ok(page) => {
+                let status = page.status.as_u16();
+                let (matched, result) = if definition.found.matches(status, &page.body) {
+                    ("found", result.with_exists(true))
+                } else if definition.not_found.matches(status, &page.body) {
+                    ("not_found", result.with_exists(false))
+                } else {
+                    match challenge::detect(&page.body) {
+                        Some(challenge) => ("none", result.with_challenge(challenge)),
+                        None => ("none", result.with_unexpected(format!("response matched neither `found` nor `not_found` (status {status})"))),
+                    }
+                };
+                let evidence = client.evidence(&page, evidence::fields([("matched", matched.to_owned())]));
+                result.with_evidence(evidence)
+            }
+            Err(e) => result.with_request_error(&e),
+        },
+        Err(e) => result.with_http_error(&e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Outcome;
+
+    fn definition() -> SiteDefinition {
+        serde_json::from_str(
+            r#"{
+                "name": "example",
+                "domain": "example.com",
+                "method": "register",
+                "request": {
+                    "method": "POST",
+                    "url": "https://example.com/check?cc={country_code}",
+                    "body": "phone={phone}"
+                },
+                "found": {"status": 200, "body_contains": "\"taken\":true"},
+                "not_found": {"body_contains": "\"taken\":false"}
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_definition() {
+        let definition = definition();
+
+        assert_eq!(definition.name, "example");
+        assert_eq!(definition.request.method, "POST");
+        assert!(!definition.frequent_rate_limit);
+        assert!(definition.validate().is_ok());
+    }
+
+    #[test]
+    fn test_render_placeholders() {
+        assert_eq!(
+            render("https://example.com/{country_code}/{phone}", "644637111", "33"),
+            "https://example.com/33/644637111"
+        );
+    }
+
+    #[test]
+    fn test_matcher() {
+        let definition = definition();
+
+        assert!(definition.found.matches(200, r#"{"taken":true}"#));
+        assert!(!definition.found.matches(500, r#"{"taken":true}"#));
+        assert!(definition.not_found.matches(404, r#"{"taken":false}"#));
+        assert!(!definition.not_found.matches(200, "<html>captcha</html>"));
+    }
+
+    #[test]
+    fn test_validate_rejects_empty_matcher() {
+        let mut definition = definition();
+        definition.not_found = Matcher::default();
+
+        assert!(definition.validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_site_rate_limited() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/check?cc=33")
+            .with_status(429)
+            .with_header("Retry-After", "30")
+            .with_body(r#"{"taken":false}"#)
+            .create_async()
+            .await;
+        let mut definition = definition();
+        definition.request.url = format!("{}/check?cc={{country_code}}", server.url());
+        let site = Site { definition, bundle_version: "1.0.0".to_owned() };
+
+        let result = check_site(&site, "644637111", "33", &reqwest::Client::new().into()).await;
+
+        mock.assert_async().await;
+        assert_eq!(result.outcome, Outcome::RateLimited);
+        assert_eq!(result.retry_after, Some(30));
+        assert!(!result.exists);
+    }
+
+    #[tokio::test]
+    async fn test_check_site_challenge_page() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/check?cc=33")
+            .with_status(403)
+            .with_body(r#"<html><div class="g-recaptcha" data-sitekey="abc"></div></html>"#)
+            .create_async()
+            .await;
+        let mut definition = definition();
+        definition.request.url = format!("{}/check?cc={{country_code}}", server.url());
+        let site = Site { definition, bundle_version: "1.0.0".to_owned() };
+
+        let result = check_site(&site, "644637111", "33", &reqwest::Client::new().into()).await;
+
+        mock.assert_async().await;
+        assert_eq!(result.outcome, Outcome::Blocked);
+        assert_eq!(result.evidence, None);
+        assert_eq!(result.challenge, Some(crate::Challenge::Captcha));
+        assert!(!result.exists);
+    }
+
+    #[tokio::test]
+    async fn test_check_site_unmatched_response() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/check?cc=33")
+            .with_body(r#"{"status":"maintenance"}"#)
+            .create_async()
+            .await;
+        let mut definition = definition();
+        definition.request.url = format!("{}/check?cc={{country_code}}", server.url());
+        let site = Site { definition, bundle_version: "1.0.0".to_owned() };
+
+        let result = check_site(&site, "644637111", "33", &reqwest::Client::new().into()).await;
+
+        mock.assert_async().await;
+        assert_eq!(result.outcome, Outcome::Unexpected);
+        assert!(!result.rate_limit);
+        assert!(!result.exists);
+        assert!(result.error.unwrap().contains("status 200"));
+    }
+
+    #[tokio::test]
+    async fn test_check_site_evidence() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/check?cc=33")
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"taken":true}"#)
+            .create_async()
+            .await;
+        let mut definition = definition();
+        definition.request.url = format!("{}/check?cc={{country_code}}", server.url());
+        let site = Site { definition, bundle_version: "1.0.0".to_owned() };
+        let client = HttpClient::from(reqwest::Client::new()).with_evidence(true);
+
+        let result = check_site(&site, "644637111", "33", &client).await;
+
+        mock.assert_async().await;
+        assert_eq!(result.outcome, Outcome::Found);
+        let evidence = result.evidence.unwrap();
+        assert_eq!(evidence.status, 200);
+        assert_eq!(evidence.url, format!("{}/check?cc=33", server.url()));
+        assert_eq!(evidence.fields["matched"], "found");
+        assert_eq!(evidence.headers["content-type"], "application/json");
+        assert_eq!(evidence.excerpt, r#"{"taken":true}"#);
+    }
+
+    #[tokio::test]
+    async fn test_check_site_network_error() {
+        let mut definition = definition();
+        definition.request.url = "http://127.0.0.1:9/check".to_owned();
+        let site = Site { definition, bundle_version: "1.0.0".to_owned() };
+
+        let result = check_site(&site, "644637111", "33", &reqwest::Client::new().into()).await;
+
+        assert_eq!(result.name, "example");
+        assert_eq!(result.bundle_version.as_deref(), Some("1.0.0"));
+        assert_eq!(result.outcome, Outcome::Error);
+        assert!(!result.exists);
+    }
+}
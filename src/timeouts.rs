@@ -0,0 +1,102 @@
+//! Connection, request and module time limits
+//!
+//! The connect and read timeouts apply to every request a module sends. The
+//! module timeout bounds a whole check, retries included; a check that runs
+//! past it is cancelled and reported as timed out.
+
+use std::future::Future;
+use std::time::Duration;
+
+use crate::CheckResult;
+use crate::config::{self, TimeoutConfig};
+
+/// Time limits for a single module
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timeouts {
+    pub connect: Duration,
+    pub read: Duration,
+    pub module: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Resolve the limits for `module`, applying its overrides on top of the defaults
+    pub fn for_module(config: &TimeoutConfig, module: &str) -> Self {
+        let overrides = config::module_override(&config.modules, module).cloned().unwrap_or_default();
+        let module_secs = overrides.module_secs.unwrap_or(config.module_secs);
+        Self {
+            connect: Duration::from_secs(overrides.connect_secs.unwrap_or(config.connect_secs)),
+            read: Duration::from_secs(overrides.read_secs.unwrap_or(config.read_secs)),
+            module: (module_secs > 0).then(|| Duration::from_secs(module_secs)),
+        }
+    }
+}
+
+/// Run `check`, replacing its result with a timed-out `fallback` if it overruns `limit`
+pub async fn with_timeout<Fut>(limit: Option<Duration>, fallback: CheckResult, check: Fut) -> CheckResult
+where
+    Fut: Future<Output = CheckResult>,
+{
+    let Some(limit) = limit else {
+        return check.await;
+    };
+    match tokio::time::timeout(limit, check).await {
+        Ok(result) => result,
+        Err(_) => fallback.with_timeout(format!("module timed out after {}s", limit.as_secs())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Outcome;
+
+    #[test]
+    fn test_timeouts_overrides() {
+        let config: crate::config::Config = toml::from_str(
+            r#"
+            [timeouts]
+            connect_secs = 3
+            module_secs = 30
+
+            [timeouts.modules.amazon]
+            read_secs = 20
+            module_secs = 0
+            "#,
+        )
+        .unwrap();
+
+        let amazon = Timeouts::for_module(&config.timeouts, "amazon");
+        assert_eq!(amazon.connect, Duration::from_secs(3));
+        assert_eq!(amazon.read, Duration::from_secs(20));
+        assert_eq!(amazon.module, None);
+
+        let snapchat = Timeouts::for_module(&config.timeouts, "snapchat");
+        assert_eq!(snapchat.read, Duration::from_secs(10));
+        assert_eq!(snapchat.module, Some(Duration::from_secs(30)));
+    }
+
+    #[tokio::test]
+    async fn test_with_timeout_cancels_slow_check() {
+        let fallback = CheckResult::new("example", "example.com", "other");
+        let check = async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            CheckResult::new("example", "example.com", "other").with_exists(true)
+        };
+
+        let result = with_timeout(Some(Duration::from_millis(10)), fallback, check).await;
+
+        assert_eq!(result.outcome, Outcome::TimedOut);
+        assert!(!result.exists);
+        assert!(result.error.unwrap().contains("timed out"));
+    }
+
+    #[tokio::test]
+    async fn test_with_timeout_passes_through() {
+        let fallback = CheckResult::new("example", "example.com", "other");
+        let check = async { CheckResult::new("example", "example.com", "other").with_exists(true) };
+
+        let result = with_timeout(Some(Duration::from_secs(5)), fallback, check).await;
+
+        assert_eq!(result.outcome, Outcome::Found);
+    }
+}
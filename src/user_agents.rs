@@ -1,3 +1,4 @@
+#[allow(dead_code)]
 pub struct UserAgents {
     pub chrome: &'static [&'static str],
     pub firefox: &'static [&'static str], 
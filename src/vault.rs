@@ -0,0 +1,201 @@
+//! Encrypted storage
+//!
+//! With a vault unlocked, history runs, `--output` files and the evidence in
+//! them are written encrypted. The key is derived with Argon2id from a
+//! passphrase (`IGNORANT_PASSPHRASE`, or a prompt) or a keyfile, and files are
+//! sealed with XChaCha20-Poly1305. An encrypted file is:
+//!
+//! ```text
+//! MAGIC (8 bytes) | salt (16 bytes) | nonce (24 bytes) | ciphertext and tag
+//! ```
+//!
+//! The header is authenticated along with the contents. A run derives one key
+//! for everything it writes; reading caches the key of each salt it meets.
+
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{Context, Result, anyhow, bail};
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use zeroize::Zeroizing;
+
+use crate::config::StorageConfig;
+
+/// Environment variable holding the storage passphrase
+pub const PASSPHRASE_ENV: &str = "IGNORANT_PASSPHRASE";
+
+const MAGIC: &[u8; 8] = b"IGNENC1\0";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+const HEADER_LEN: usize = MAGIC.len() + SALT_LEN + NONCE_LEN;
+
+/// Unlocked key material for reading and writing encrypted files
+pub struct Vault {
+    secret: Zeroizing<Vec<u8>>,
+    /// Salt of everything this vault writes
+    salt: [u8; SALT_LEN],
+    /// Derived keys by salt
+    keys: Mutex<HashMap<[u8; SALT_LEN], Zeroizing<[u8; 32]>>>,
+}
+
+impl Vault {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Self {
+        let mut salt = [0; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        Self {
+            secret: Zeroizing::new(secret.into()),
+            salt,
+            keys: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Unlock storage with the passphrase from the environment, the keyfile
+    /// or, on a terminal, a prompt
+    ///
+    /// Returns `None` when no secret is available and encryption is not required.
+    pub fn unlock(config: &StorageConfig) -> Result<Option<Self>> {
+        if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV)
+            && !passphrase.is_empty()
+        {
+            return Ok(Some(Self::new(passphrase)));
+        }
+        if let Some(keyfile) = &config.keyfile {
+            let secret = fs::read(keyfile).with_context(|| format!("failed to read keyfile {}", keyfile.display()))?;
+            if secret.is_empty() {
+                bail!("keyfile {} is empty", keyfile.display());
+            }
+            return Ok(Some(Self::new(secret)));
+        }
+        if !config.encrypt {
+            return Ok(None);
+        }
+        if !std::io::stdin().is_terminal() {
+            bail!("[storage] encrypt is on: set {PASSPHRASE_ENV} or [storage] keyfile to unlock storage");
+        }
+        let passphrase = Zeroizing::new(rpassword::prompt_password("Storage passphrase: ")?);
+        if passphrase.is_empty() {
+            bail!("empty passphrase");
+        }
+        Ok(Some(Self::new(passphrase.as_bytes())))
+    }
+
+    fn cipher(&self, salt: [u8; SALT_LEN]) -> Result<XChaCha20Poly1305> {
+        let mut keys = self.keys.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let key = match keys.entry(salt) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let mut key = Zeroizing::new([0; 32]);
+                Argon2::default()
+                    .hash_password_into(&self.secret, &salt, key.as_mut())
+                    .map_err(|e| anyhow!("key derivation failed: {e}"))?;
+                entry.insert(key)
+            }
+        };
+        Ok(XChaCha20Poly1305::new(Key::from_slice(key.as_ref())))
+    }
+
+    /// Encrypt `plaintext` into the file format above
+    pub fn seal(&self, plaintext: &[u8]) -> Result<Vec<u8>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut sealed = Vec::with_capacity(HEADER_LEN + plaintext.len() + 16);
+        sealed.extend_from_slice(MAGIC);
+        sealed.extend_from_slice(&self.salt);
+        sealed.extend_from_slice(&nonce);
+        let ciphertext = self
+            .cipher(self.salt)?
+            .encrypt(&nonce, Payload { msg: plaintext, aad: &sealed })
+            .map_err(|_| anyhow!("encryption failed"))?;
+        sealed.extend_from_slice(&ciphertext);
+        Ok(sealed)
+    }
+
+    /// Decrypt the contents of an encrypted file
+    pub fn open(&self, sealed: &[u8]) -> Result<Vec<u8>> {
+        if !is_encrypted(sealed) || sealed.len() < HEADER_LEN {
+            bail!("not an encrypted file");
+        }
+        let (header, ciphertext) = sealed.split_at(HEADER_LEN);
+        let salt: [u8; SALT_LEN] = header[MAGIC.len()..MAGIC.len() + SALT_LEN].try_into()?;
+        let nonce = XNonce::from_slice(&header[MAGIC.len() + SALT_LEN..]);
+        self.cipher(salt)?
+            .decrypt(nonce, Payload { msg: ciphertext, aad: header })
+            .map_err(|_| anyhow!("decryption failed: wrong passphrase or keyfile, or the file was modified"))
+    }
+}
+
+/// Whether file contents are encrypted
+pub fn is_encrypted(contents: &[u8]) -> bool {
+    contents.starts_with(MAGIC)
+}
+
+/// Write a text file, encrypted when a vault is unlocked
+pub fn write(path: &Path, contents: &str, vault: Option<&Vault>) -> Result<()> {
+    let bytes = match vault {
+        Some(vault) => vault.seal(contents.as_bytes())?,
+        None => contents.as_bytes().to_vec(),
+    };
+    fs::write(path, bytes).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Read a text file, decrypting it if it is encrypted
+pub fn read(path: &Path, vault: Option<&Vault>) -> Result<String> {
+    let bytes = fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    if !is_encrypted(&bytes) {
+        return String::from_utf8(bytes).with_context(|| format!("{} is not a text file", path.display()));
+    }
+    let Some(vault) = vault else {
+        bail!("{} is encrypted: set {PASSPHRASE_ENV} or [storage] keyfile to unlock it", path.display());
+    };
+    let plaintext = vault.open(&bytes).with_context(|| format!("failed to decrypt {}", path.display()))?;
+    String::from_utf8(plaintext).with_context(|| format!("{} is not a text file", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let vault = Vault::new("correct horse battery staple");
+
+        let sealed = vault.seal(b"+33 644637111").unwrap();
+
+        assert!(is_encrypted(&sealed));
+        assert!(!sealed.windows(9).any(|window| window == b"644637111"));
+        assert_eq!(vault.open(&sealed).unwrap(), b"+33 644637111");
+        // Another vault with the same secret derives the key from the stored salt
+        assert_eq!(Vault::new("correct horse battery staple").open(&sealed).unwrap(), b"+33 644637111");
+    }
+
+    #[test]
+    fn test_wrong_secret_and_tampering() {
+        let sealed = Vault::new("correct horse battery staple").seal(b"results").unwrap();
+
+        assert!(Vault::new("wrong").open(&sealed).is_err());
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(Vault::new("correct horse battery staple").open(&tampered).is_err());
+    }
+
+    #[test]
+    fn test_read_and_write_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("results.jsonl");
+        let vault = Vault::new("secret");
+
+        write(&path, "{}\n", Some(&vault)).unwrap();
+
+        assert!(is_encrypted(&fs::read(&path).unwrap()));
+        assert_eq!(read(&path, Some(&vault)).unwrap(), "{}\n");
+        assert!(read(&path, None).unwrap_err().to_string().contains("is encrypted"));
+        write(&path, "{}\n", None).unwrap();
+        assert_eq!(read(&path, Some(&vault)).unwrap(), "{}\n");
+    }
+}